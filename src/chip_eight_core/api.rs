@@ -1,3 +1,79 @@
+use super::instruction::Instruction;
+
 pub trait Tick {
     fn tick(&mut self) -> ();
 }
+
+// What a single step of the processor did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed { pc: u16, instruction: Instruction },
+    // FX0A is holding the CPU until a key is pressed.
+    WaitingForKey,
+    // DXYN with the display-wait quirk is holding the CPU until the next frame.
+    WaitingForFrame,
+    // A whole frame went by with no instructions to run, at under 60 per second.
+    Idle,
+}
+
+// A rectangle of the framebuffer, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// A borrowed view of the framebuffer handed to a display whenever it changes.
+pub struct Frame<'a> {
+    pub width: usize,
+    pub height: usize,
+    // Row-major, `true` for a lit pixel.
+    pub pixels: &'a [bool],
+    // The part of the framebuffer that changed since the last presented frame.
+    pub dirty: Region,
+}
+
+impl Frame<'_> {
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        return self.pixels[x + y * self.width];
+    }
+}
+
+// Anything that can show the framebuffer: a terminal, a window, a test harness...
+pub trait Display {
+    fn present(&mut self, frame: &Frame) -> ();
+}
+
+// Anything that can sound the buzzer, told whenever it starts or stops.
+pub trait Audio {
+    fn set_playing(&mut self, playing: bool) -> ();
+}
+
+// A read-only view of the CPU and memory, for debuggers, cheat tools and tests. Change the
+// machine through `ChipEight`'s setters instead.
+pub struct MachineState<'a> {
+    // V0 to VF.
+    pub registers: [u8; 16],
+    pub index_register: u16,
+    pub program_counter: u16,
+    pub stack_pointer: usize,
+    // Return addresses, bottom of the stack first, `stack_pointer` of them.
+    pub stack: &'a [u16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    // The register FX0A will put the key in, while it holds the CPU.
+    pub waiting_for_key: Option<usize>,
+    // The opcode most recently fetched.
+    pub opcode: u16,
+    pub memory: &'a [u8],
+}
+
+impl MachineState<'_> {
+    // The opcode at `address`, if both bytes are in memory.
+    pub fn opcode_at(&self, address: u16) -> Option<u16> {
+        let bytes: &[u8] = self.memory.get(address as usize..address as usize + 2)?;
+        return Some(u16::from_be_bytes([bytes[0], bytes[1]]));
+    }
+}
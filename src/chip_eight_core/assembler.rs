@@ -0,0 +1,664 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::instruction::{self, Instruction};
+use super::memory::{PROGRAM_END, PROGRAM_START};
+
+// Guards against a file that (indirectly) includes itself.
+const MAX_INCLUDE_DEPTH: usize = 16;
+// Guards against constants defined in terms of each other.
+const MAX_CONSTANT_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    pub location: Location,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}",
+            self.location.file, self.location.line, self.location.column, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+fn error<T>(location: &Location, message: String) -> Result<T, AssembleError> {
+    return Err(AssembleError { location: location.clone(), message });
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    Number(i64),
+    Text(String),
+    Symbol(char),
+}
+
+#[derive(Clone, Debug)]
+enum Term {
+    Number(i64),
+    Name(String),
+}
+
+// A sum of numbers and symbols, e.g. `sprites + 5` or `-1`.
+#[derive(Clone, Debug)]
+struct Expression {
+    terms: Vec<(i64, Term, Location)>,
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Register(usize),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    Bcd,
+    Value(Expression),
+}
+
+enum Item {
+    Instruction { mnemonic: String, operands: Vec<Operand> },
+    Data { width: usize, values: Vec<Expression> },
+    Bytes(Vec<u8>),
+}
+
+struct Statement {
+    location: Location,
+    item: Item,
+}
+
+// Two passes: the first lays out every statement and records where the labels land, the
+// second evaluates operands (now that forward references are known) and encodes them.
+struct Assembler {
+    statements: Vec<Statement>,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, Expression>,
+    address: usize,
+}
+
+// Assemble source text, `name` is only used in error messages and includes are resolved
+// against the working directory.
+pub fn assemble(source: &str, name: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut assembler: Assembler = Assembler::new();
+    assembler.add_source(source, name, Path::new("."), 0)?;
+    return assembler.finish(name);
+}
+
+pub fn assemble_file(path: &str) -> Result<Vec<u8>, AssembleError> {
+    let location: Location = Location { file: path.to_string(), line: 0, column: 0 };
+    let source: String = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(reason) => return error(&location, format!("could not read file: {}", reason)),
+    };
+    let directory: PathBuf = Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut assembler: Assembler = Assembler::new();
+    assembler.add_source(&source, path, &directory, 0)?;
+    return assembler.finish(path);
+}
+
+fn tokenize(text: &str, location: &Location) -> Result<Vec<(Token, Location)>, AssembleError> {
+    let characters: Vec<char> = text.chars().collect();
+    let mut tokens: Vec<(Token, Location)> = Vec::new();
+    let mut i: usize = 0;
+    while i < characters.len() {
+        let character: char = characters[i];
+        let here: Location = Location { column: i + 1, ..location.clone() };
+        if character == ';' { break; }
+        if character.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let start: usize = i;
+        let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+        if character == '"' {
+            i += 1;
+            while i < characters.len() && characters[i] != '"' { i += 1; }
+            if i == characters.len() {
+                return error(&here, "unterminated string".to_string());
+            }
+            tokens.push((Token::Text(characters[start + 1..i].iter().collect()), here));
+            i += 1;
+        } else if character.is_ascii_digit() || (character == '$' && i + 1 < characters.len()) {
+            i += 1;
+            while i < characters.len() && is_name(characters[i]) { i += 1; }
+            let word: String = characters[start..i].iter().collect();
+            match parse_number(&word) {
+                Some(value) => tokens.push((Token::Number(value), here)),
+                None => return error(&here, format!("invalid number `{}`", word)),
+            }
+        } else if is_name(character) || (character == ':' && i + 1 < characters.len()
+                && characters[i + 1].is_alphabetic() && !(i > 0 && is_name(characters[i - 1]))) {
+            // Octo directives such as `:const` are read as a single name, but a colon right
+            // after a name ends a label, as in `start:JP start`.
+            i += 1;
+            while i < characters.len() && is_name(characters[i]) { i += 1; }
+            tokens.push((Token::Name(characters[start..i].iter().collect()), here));
+        } else if ",:[]=+-".contains(character) {
+            tokens.push((Token::Symbol(character), here));
+            i += 1;
+        } else {
+            return error(&here, format!("unexpected character `{}`", character));
+        }
+    }
+    return Ok(tokens);
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    let lower: String = word.to_ascii_lowercase();
+    return if let Some(digits) = lower.strip_prefix("0x").or(lower.strip_prefix('$')) {
+        i64::from_str_radix(digits, 16).ok()
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        i64::from_str_radix(digits, 2).ok()
+    } else {
+        lower.parse::<i64>().ok()
+    };
+}
+
+fn register_index(name: &str) -> Option<usize> {
+    let mut characters = name.chars();
+    let prefix: char = characters.next()?;
+    let digit: char = characters.next()?;
+    if !prefix.eq_ignore_ascii_case(&'v') || characters.next().is_some() { return None; }
+    return digit.to_digit(16).map(|index| index as usize);
+}
+
+// A sprite row such as "..XX..XX", lit pixels being any of `#X1*`.
+fn sprite_row(row: &str, location: &Location) -> Result<u8, AssembleError> {
+    if row.chars().count() > 8 {
+        return error(location, format!("sprite row `{}` is wider than 8 pixels", row));
+    }
+    let mut byte: u8 = 0;
+    for (column, pixel) in row.chars().enumerate() {
+        match pixel {
+            '#' | 'X' | 'x' | '1' | '*' => byte |= 0x80 >> column,
+            '.' | '0' | ' ' | '_' => {}
+            _ => return error(location, format!("invalid sprite pixel `{}`", pixel)),
+        }
+    }
+    return Ok(byte);
+}
+
+fn parse_expression(tokens: &[(Token, Location)], location: &Location)
+        -> Result<Expression, AssembleError> {
+    let mut terms: Vec<(i64, Term, Location)> = Vec::new();
+    let mut sign: i64 = 1;
+    let mut expecting_term: bool = true;
+    for (token, here) in tokens {
+        match (token, expecting_term) {
+            (Token::Symbol('-'), true) => sign = -sign,
+            (Token::Symbol('+'), true) => {}
+            (Token::Number(value), true) => terms.push((sign, Term::Number(*value), here.clone())),
+            (Token::Name(name), true) => terms.push((sign, Term::Name(name.clone()), here.clone())),
+            (Token::Symbol('+'), false) => sign = 1,
+            (Token::Symbol('-'), false) => sign = -1,
+            _ => return error(here, "unexpected token in expression".to_string()),
+        }
+        if matches!(token, Token::Number(_) | Token::Name(_)) {
+            expecting_term = false;
+            sign = 1;
+        } else {
+            expecting_term = true;
+        }
+    }
+    if terms.is_empty() || expecting_term {
+        return error(location, "expected a value".to_string());
+    }
+    return Ok(Expression { terms });
+}
+
+fn parse_operand(tokens: &[(Token, Location)], location: &Location)
+        -> Result<Operand, AssembleError> {
+    if let [(Token::Name(name), _)] = tokens {
+        if let Some(index) = register_index(name) {
+            return Ok(Operand::Register(index));
+        }
+        let operand: Option<Operand> = match name.to_ascii_uppercase().as_str() {
+            "I" => Some(Operand::I),
+            "DT" => Some(Operand::DelayTimer),
+            "ST" => Some(Operand::SoundTimer),
+            "K" => Some(Operand::Key),
+            "F" => Some(Operand::Font),
+            "B" => Some(Operand::Bcd),
+            _ => None,
+        };
+        if let Some(operand) = operand {
+            return Ok(operand);
+        }
+    }
+    if let [(Token::Symbol('['), _), (Token::Name(name), _), (Token::Symbol(']'), _)] = tokens {
+        if name.eq_ignore_ascii_case("i") {
+            return Ok(Operand::IndirectI);
+        }
+    }
+    return Ok(Operand::Value(parse_expression(tokens, location)?));
+}
+
+// Split a list of tokens on top-level commas.
+fn split_operands(tokens: &[(Token, Location)]) -> Vec<&[(Token, Location)]> {
+    if tokens.is_empty() { return Vec::new(); }
+    return tokens.split(|(token, _)| *token == Token::Symbol(',')).collect();
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler {
+            statements: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            address: PROGRAM_START,
+        }
+    }
+
+    fn add_source(&mut self, source: &str, file: &str, directory: &Path, depth: usize)
+            -> Result<(), AssembleError> {
+        for (number, text) in source.lines().enumerate() {
+            let location: Location =
+                Location { file: file.to_string(), line: number + 1, column: 1 };
+            let tokens: Vec<(Token, Location)> = tokenize(text, &location)?;
+            self.add_line(&tokens, directory, depth)?;
+        }
+        return Ok(());
+    }
+
+    fn add_line(&mut self, tokens: &[(Token, Location)], directory: &Path, depth: usize)
+            -> Result<(), AssembleError> {
+        let mut tokens: &[(Token, Location)] = tokens;
+        // Labels, either `name:` or Octo's `: name`, may share a line with a statement.
+        while let [(Token::Name(name), here), (Token::Symbol(':'), _), ..]
+                | [(Token::Symbol(':'), _), (Token::Name(name), here), ..] = tokens {
+            self.define_label(name, here)?;
+            tokens = &tokens[2..];
+        }
+        let Some(((first, here), rest)) = tokens.split_first() else { return Ok(()); };
+
+        if let (Token::Name(name), [(Token::Symbol('='), _), value @ ..]) = (first, rest) {
+            return self.define_constant(name, value, here);
+        }
+        let Token::Name(keyword) = first else {
+            return error(here, "expected a label, directive or instruction".to_string());
+        };
+        match keyword.to_ascii_lowercase().as_str() {
+            "define" | ":const" => match rest {
+                [(Token::Name(name), _), value @ ..] => self.define_constant(name, value, here),
+                _ => error(here, format!("`{}` needs a name and a value", keyword)),
+            },
+            "db" | "byte" => self.add_data(1, rest, here),
+            "dw" | "word" => self.add_data(2, rest, here),
+            "sprite" => {
+                let mut bytes: Vec<u8> = Vec::new();
+                for operand in split_operands(rest) {
+                    match operand {
+                        [(Token::Text(row), at)] => bytes.push(sprite_row(row, at)?),
+                        _ => return error(here, "sprite rows must be strings".to_string()),
+                    }
+                }
+                self.push(here, Item::Bytes(bytes))
+            }
+            "include" => match rest {
+                [(Token::Text(path), at)] => self.include(path, at, directory, depth),
+                _ => error(here, "include needs a quoted file name".to_string()),
+            },
+            _ => {
+                let mut operands: Vec<Operand> = Vec::new();
+                for operand in split_operands(rest) {
+                    operands.push(parse_operand(operand, here)?);
+                }
+                let mnemonic: String = keyword.to_ascii_uppercase();
+                self.push(here, Item::Instruction { mnemonic, operands })
+            }
+        }
+    }
+
+    fn define_label(&mut self, name: &str, location: &Location) -> Result<(), AssembleError> {
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return error(location, format!("`{}` is already defined", name));
+        }
+        self.labels.insert(name.to_string(), self.address as u16);
+        return Ok(());
+    }
+
+    fn define_constant(&mut self, name: &str, value: &[(Token, Location)], location: &Location)
+            -> Result<(), AssembleError> {
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return error(location, format!("`{}` is already defined", name));
+        }
+        let expression: Expression = parse_expression(value, location)?;
+        self.constants.insert(name.to_string(), expression);
+        return Ok(());
+    }
+
+    fn add_data(&mut self, width: usize, tokens: &[(Token, Location)], location: &Location)
+            -> Result<(), AssembleError> {
+        let mut values: Vec<Expression> = Vec::new();
+        for operand in split_operands(tokens) {
+            match operand {
+                // Strings are stored as their ASCII bytes.
+                [(Token::Text(text), _)] if width == 1 => {
+                    for byte in text.bytes() {
+                        let terms = vec![(1, Term::Number(byte as i64), location.clone())];
+                        values.push(Expression { terms });
+                    }
+                }
+                _ => values.push(parse_expression(operand, location)?),
+            }
+        }
+        if values.is_empty() {
+            return error(location, "data directive needs at least one value".to_string());
+        }
+        return self.push(location, Item::Data { width, values });
+    }
+
+    fn include(&mut self, path: &str, location: &Location, directory: &Path, depth: usize)
+            -> Result<(), AssembleError> {
+        if depth >= MAX_INCLUDE_DEPTH {
+            return error(location, "includes are nested too deeply".to_string());
+        }
+        let full_path: PathBuf = directory.join(path);
+        let source: String = match fs::read_to_string(&full_path) {
+            Ok(source) => source,
+            Err(reason) => return error(location,
+                format!("could not include {}: {}", full_path.display(), reason)),
+        };
+        let directory: PathBuf = full_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        return self.add_source(&source, &full_path.display().to_string(), &directory, depth + 1);
+    }
+
+    fn push(&mut self, location: &Location, item: Item) -> Result<(), AssembleError> {
+        self.address += match &item {
+            Item::Instruction { .. } => 2,
+            Item::Data { width, values } => width * values.len(),
+            Item::Bytes(bytes) => bytes.len(),
+        };
+        if self.address > PROGRAM_END + 1 {
+            return error(location, "program does not fit in memory".to_string());
+        }
+        self.statements.push(Statement { location: location.clone(), item });
+        return Ok(());
+    }
+
+    fn finish(&self, file: &str) -> Result<Vec<u8>, AssembleError> {
+        let mut output: Vec<u8> = Vec::new();
+        for statement in &self.statements {
+            match &statement.item {
+                Item::Instruction { mnemonic, operands } => {
+                    let instruction: Instruction =
+                        self.build(mnemonic, operands, &statement.location)?;
+                    output.extend_from_slice(&instruction::encode(&instruction).to_be_bytes());
+                }
+                Item::Data { width: 1, values } => for value in values {
+                    output.push(self.byte(value)?);
+                },
+                Item::Data { values, .. } => for value in values {
+                    let word: i64 = self.evaluate(value, 0)?;
+                    if !(-0x8000..=0xFFFF).contains(&word) {
+                        return error(&value.terms[0].2, format!("{} does not fit in a word", word));
+                    }
+                    output.extend_from_slice(&(word as u16).to_be_bytes());
+                },
+                Item::Bytes(bytes) => output.extend_from_slice(bytes),
+            }
+        }
+        if output.is_empty() {
+            let location: Location = Location { file: file.to_string(), line: 0, column: 0 };
+            return error(&location, "nothing to assemble".to_string());
+        }
+        return Ok(output);
+    }
+
+    fn evaluate(&self, expression: &Expression, depth: usize) -> Result<i64, AssembleError> {
+        let mut total: i64 = 0;
+        for (sign, term, location) in &expression.terms {
+            let value: i64 = match term {
+                Term::Number(value) => *value,
+                Term::Name(name) => if let Some(address) = self.labels.get(name) {
+                    *address as i64
+                } else if let Some(constant) = self.constants.get(name) {
+                    if depth >= MAX_CONSTANT_DEPTH {
+                        return error(location, format!("`{}` is defined in terms of itself", name));
+                    }
+                    self.evaluate(constant, depth + 1)?
+                } else {
+                    return error(location, format!("undefined symbol `{}`", name));
+                },
+            };
+            let Some(sum) = sign.checked_mul(value).and_then(|term| total.checked_add(term)) else {
+                return error(location, "expression overflows".to_string());
+            };
+            total = sum;
+        }
+        return Ok(total);
+    }
+
+    fn ranged(&self, expression: &Expression, low: i64, high: i64, what: &str)
+            -> Result<i64, AssembleError> {
+        let value: i64 = self.evaluate(expression, 0)?;
+        if value < low || value > high {
+            return error(&expression.terms[0].2, format!("{} does not fit in {}", value, what));
+        }
+        return Ok(value);
+    }
+
+    fn address(&self, expression: &Expression) -> Result<u16, AssembleError> {
+        return Ok(self.ranged(expression, 0, 0xFFF, "a 12-bit address")? as u16);
+    }
+
+    // Negative bytes are allowed and stored as two's complement, e.g. `ADD V0, -1`.
+    fn byte(&self, expression: &Expression) -> Result<u8, AssembleError> {
+        return Ok(self.ranged(expression, -0x80, 0xFF, "a byte")? as u8);
+    }
+
+    fn nibble(&self, expression: &Expression) -> Result<u8, AssembleError> {
+        return Ok(self.ranged(expression, 0, 0xF, "a nibble")? as u8);
+    }
+
+    fn build(&self, mnemonic: &str, operands: &[Operand], location: &Location)
+            -> Result<Instruction, AssembleError> {
+        use Operand::*;
+        let instruction: Instruction = match (mnemonic, operands) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SYS", [Value(address)]) => Instruction::Sys(self.address(address)?),
+            ("JP", [Value(address)]) => Instruction::Jp(self.address(address)?),
+            ("JP", [Register(0), Value(address)]) => Instruction::JpV0(self.address(address)?),
+            ("CALL", [Value(address)]) => Instruction::Call(self.address(address)?),
+            ("SE", [Register(x), Register(y)]) => Instruction::SeVxVy { x: *x, y: *y },
+            ("SE", [Register(x), Value(kk)]) => Instruction::SeVxByte { x: *x, kk: self.byte(kk)? },
+            ("SNE", [Register(x), Register(y)]) => Instruction::SneVxVy { x: *x, y: *y },
+            ("SNE", [Register(x), Value(kk)]) =>
+                Instruction::SneVxByte { x: *x, kk: self.byte(kk)? },
+            ("LD", [Register(x), Value(kk)]) => Instruction::LdVxByte { x: *x, kk: self.byte(kk)? },
+            ("LD", [Register(x), Register(y)]) => Instruction::LdVxVy { x: *x, y: *y },
+            ("LD", [I, Value(address)]) => Instruction::LdI(self.address(address)?),
+            ("LD", [Register(x), DelayTimer]) => Instruction::LdVxDt { x: *x },
+            ("LD", [Register(x), Key]) => Instruction::LdVxK { x: *x },
+            ("LD", [DelayTimer, Register(x)]) => Instruction::LdDtVx { x: *x },
+            ("LD", [SoundTimer, Register(x)]) => Instruction::LdStVx { x: *x },
+            ("LD", [Font, Register(x)]) => Instruction::LdFVx { x: *x },
+            ("LD", [Bcd, Register(x)]) => Instruction::LdBVx { x: *x },
+            ("LD", [IndirectI, Register(x)]) => Instruction::LdIVx { x: *x },
+            ("LD", [Register(x), IndirectI]) => Instruction::LdVxI { x: *x },
+            ("ADD", [Register(x), Value(kk)]) =>
+                Instruction::AddVxByte { x: *x, kk: self.byte(kk)? },
+            ("ADD", [Register(x), Register(y)]) => Instruction::AddVxVy { x: *x, y: *y },
+            ("ADD", [I, Register(x)]) => Instruction::AddIVx { x: *x },
+            ("OR", [Register(x), Register(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [Register(x), Register(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [Register(x), Register(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [Register(x), Register(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("SUBN", [Register(x), Register(y)]) => Instruction::Subn { x: *x, y: *y },
+            ("SHR", [Register(x)]) => Instruction::Shr { x: *x, y: *x },
+            ("SHR", [Register(x), Register(y)]) => Instruction::Shr { x: *x, y: *y },
+            ("SHL", [Register(x)]) => Instruction::Shl { x: *x, y: *x },
+            ("SHL", [Register(x), Register(y)]) => Instruction::Shl { x: *x, y: *y },
+            ("RND", [Register(x), Value(kk)]) => Instruction::Rnd { x: *x, kk: self.byte(kk)? },
+            ("DRW", [Register(x), Register(y), Value(n)]) =>
+                Instruction::Drw { x: *x, y: *y, n: self.nibble(n)? },
+            ("SKP", [Register(x)]) => Instruction::Skp { x: *x },
+            ("SKNP", [Register(x)]) => Instruction::Sknp { x: *x },
+            ("CLS" | "RET" | "SYS" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND"
+                | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP", _) =>
+                return error(location, format!("invalid operands for {}", mnemonic)),
+            _ => return error(location, format!("unknown instruction `{}`", mnemonic)),
+        };
+        return Ok(instruction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble_ok(source: &str) -> Vec<u8> {
+        return assemble(source, "test.asm").unwrap_or_else(|error| panic!("{}", error));
+    }
+
+    fn assemble_err(source: &str) -> AssembleError {
+        return assemble(source, "test.asm").expect_err("should not assemble");
+    }
+
+    // A directory of its own under the system's temporary directory.
+    fn scratch_directory(name: &str) -> PathBuf {
+        let directory: PathBuf = std::env::temp_dir()
+            .join(format!("chip_eight_assembler_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        return directory;
+    }
+
+    #[test]
+    fn assembles_every_operand_form() {
+        let source: &str = "\
+            CLS\n RET\n SYS 0x123\n JP 0x2AB\n JP V0, 0x300\n CALL 0x400\n\
+            SE V1, 2\n SE V1, V2\n SNE V3, 0xFF\n SNE V3, V4\n\
+            LD V5, 6\n LD V5, V6\n LD I, 0x500\n LD V7, DT\n LD V8, K\n LD DT, V9\n LD ST, VA\n\
+            LD F, VB\n LD B, VC\n LD [I], VD\n LD VE, [I]\n\
+            ADD V0, 1\n ADD V0, V1\n ADD I, V2\n OR V0, V1\n AND V0, V1\n XOR V0, V1\n\
+            SUB V0, V1\n SUBN V0, V1\n SHR V3\n SHR V3, V4\n SHL V5\n SHL V5, V6\n\
+            RND V0, 0x0F\n DRW V0, V1, 5\n SKP V2\n SKNP V3\n";
+        let opcodes: Vec<u16> = assemble_ok(source).chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(opcodes, [
+            0x00E0, 0x00EE, 0x0123, 0x12AB, 0xB300, 0x2400,
+            0x3102, 0x5120, 0x43FF, 0x9340,
+            0x6506, 0x8560, 0xA500, 0xF707, 0xF80A, 0xF915, 0xFA18,
+            0xFB29, 0xFC33, 0xFD55, 0xFE65,
+            0x7001, 0x8014, 0xF21E, 0x8011, 0x8012, 0x8013,
+            0x8015, 0x8017, 0x8336, 0x8346, 0x855E, 0x856E,
+            0xC00F, 0xD015, 0xE29E, 0xE3A1,
+        ]);
+    }
+
+    #[test]
+    fn resolves_labels_forwards_and_backwards() {
+        let source: &str = "start: JP end\n  LD I, data + 1\nend: JP start\ndata: db 1, 2";
+        assert_eq!(assemble_ok(source), [0x12, 0x04, 0xA2, 0x07, 0x12, 0x00, 1, 2]);
+        // Octo style labels, sharing a line with the statement.
+        assert_eq!(assemble_ok(": main JP main"), [0x12, 0x00]);
+        // No space between the label and the statement.
+        assert_eq!(assemble_ok("CLS
+start:JP start"), [0x00, 0xE0, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn evaluates_expressions_and_constants() {
+        let source: &str = "\
+            base = 0x300\n\
+            define offset base + 4 - 1\n\
+            :const step -offset + offset + 2\n\
+            LD I, offset\n ADD V0, -1\n LD V1, step - -1\n LD V2, $1F + 0b11";
+        assert_eq!(assemble_ok(source), [0xA3, 0x03, 0x70, 0xFF, 0x61, 0x03, 0x62, 0x22]);
+    }
+
+    #[test]
+    fn rejects_constants_defined_in_terms_of_themselves() {
+        let error: AssembleError = assemble_err("a = b\nb = a + 1\nLD V0, a");
+        assert!(error.message.contains("defined in terms of itself"), "{}", error);
+        assert!(assemble_err("x = 1\nx: CLS").message.contains("already defined"));
+    }
+
+    #[test]
+    fn stores_data_and_sprites() {
+        let source: &str = "\
+            db \"AB\", 3, -1\n dw 0x1234, -2\n\
+            sprite \"#..XX..1\", \"*_______\", \"\"";
+        assert_eq!(assemble_ok(source),
+            [0x41, 0x42, 3, 0xFF, 0x12, 0x34, 0xFF, 0xFE, 0x99, 0x80, 0x00]);
+        let error: AssembleError = assemble_err("sprite \"#########\"");
+        assert!(error.message.contains("wider than 8 pixels"), "{}", error);
+        assert!(assemble_err("sprite \"#..o\"").message.contains("invalid sprite pixel"));
+    }
+
+    #[test]
+    fn checks_operands_fit_their_fields() {
+        assert_eq!(assemble_err("JP 0x1000").message, "4096 does not fit in a 12-bit address");
+        assert_eq!(assemble_err("LD V0, 256").message, "256 does not fit in a byte");
+        assert_eq!(assemble_err("LD V0, -129").message, "-129 does not fit in a byte");
+        assert_eq!(assemble_err("DRW V0, V1, 16").message, "16 does not fit in a nibble");
+        assert_eq!(assemble_err("dw 0x10000").message, "65536 does not fit in a word");
+        let error: AssembleError = assemble_err("LD V0, 0x7FFFFFFFFFFFFFFF + 1");
+        assert_eq!((error.location.column, error.message.as_str()), (29, "expression overflows"));
+        assert_eq!(assemble_err("x = 0 - 0x7FFFFFFFFFFFFFFF - 2
+LD V0, x").message,
+            "expression overflows");
+        assert_eq!(assemble_err("ADD V0, I").message, "invalid operands for ADD");
+        assert_eq!(assemble_err("MOV V0, 1").message, "unknown instruction `MOV`");
+    }
+
+    #[test]
+    fn reports_where_errors_are() {
+        let error: AssembleError = assemble_err("CLS\n  LD V0, missing");
+        assert_eq!(error.location, Location { file: "test.asm".to_string(), line: 2, column: 10 });
+        assert_eq!(error.message, "undefined symbol `missing`");
+        assert_eq!(error.to_string(), "test.asm:2:10: undefined symbol `missing`");
+
+        let error: AssembleError = assemble_err("CLS ; fine\nCLS @");
+        assert_eq!((error.location.line, error.location.column), (2, 5));
+        let error: AssembleError = assemble_err("db \"open");
+        assert_eq!((error.location.column, error.message.as_str()), (4, "unterminated string"));
+        assert_eq!(assemble_err("; nothing\n").message, "nothing to assemble");
+    }
+
+    #[test]
+    fn stops_at_the_end_of_memory() {
+        // 0x200 to 0xFFF holds 1792 instructions.
+        assert_eq!(assemble_ok(&"CLS\n".repeat(1792)).len(), 0xE00);
+        let error: AssembleError = assemble_err(&"CLS\n".repeat(1793));
+        assert_eq!((error.location.line, error.message.as_str()),
+            (1793, "program does not fit in memory"));
+    }
+
+    #[test]
+    fn includes_files_relative_to_the_includer() {
+        let directory: PathBuf = scratch_directory("include");
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        fs::write(directory.join("main.asm"), "include \"lib/sprites.asm\"\nLD I, digit").unwrap();
+        fs::write(directory.join("lib/sprites.asm"), "JP over\ndigit: db 0xF0\nover:").unwrap();
+        let path: String = directory.join("main.asm").display().to_string();
+        assert_eq!(assemble_file(&path).unwrap(), [0x12, 0x03, 0xF0, 0xA2, 0x02]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn limits_include_depth() {
+        let directory: PathBuf = scratch_directory("depth");
+        fs::write(directory.join("self.asm"), "CLS\ninclude \"self.asm\"").unwrap();
+        let path: String = directory.join("self.asm").display().to_string();
+        let error: AssembleError = assemble_file(&path).expect_err("includes itself forever");
+        assert_eq!(error.message, "includes are nested too deeply");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
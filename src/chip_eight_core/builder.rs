@@ -0,0 +1,111 @@
+use super::api::{Audio, Display, Frame};
+use super::chip_eight::ChipEight;
+use super::graphics::Graphics;
+use super::keypad::Keypad;
+use super::memory::{Memory, SPRITE_DATA};
+use super::processor::Processor;
+use super::quirks::Quirks;
+use super::random::{self, RandomMode};
+use super::scheduler::{Pacing, Scheduler, Speed};
+use super::sound::Sound;
+
+// Sets up a machine. Everything has a default, so `ChipEightBuilder::new().build()` is a COSMAC
+// VIP at 700 instructions per second, held to real time, with nowhere to show or sound anything.
+pub struct ChipEightBuilder {
+    quirks: Quirks,
+    speed: Speed,
+    pacing: Pacing,
+    font: [u8; 80],
+    random_mode: RandomMode,
+    // Picked at random when not given.
+    seed: Option<u64>,
+    display: Box<dyn Display>,
+    audio: Box<dyn Audio>,
+}
+
+// For a machine nobody watches or listens to, the framebuffer can still be read back.
+struct NoDisplay;
+
+impl Display for NoDisplay {
+    fn present(&mut self, _frame: &Frame) {}
+}
+
+struct NoAudio;
+
+impl Audio for NoAudio {
+    fn set_playing(&mut self, _playing: bool) {}
+}
+
+impl ChipEightBuilder {
+    pub fn new() -> ChipEightBuilder {
+        ChipEightBuilder {
+            quirks: Quirks::new(),
+            speed: Speed::InstructionsPerSecond(700),
+            pacing: Pacing::RealTime,
+            font: SPRITE_DATA,
+            random_mode: RandomMode::Xorshift,
+            seed: None,
+            display: Box::new(NoDisplay),
+            audio: Box::new(NoAudio),
+        }
+    }
+    pub fn quirks(mut self, quirks: Quirks) -> ChipEightBuilder {
+        self.quirks = quirks;
+        return self;
+    }
+    pub fn instructions_per_second(self, instructions: u32) -> ChipEightBuilder {
+        return self.speed(Speed::InstructionsPerSecond(instructions));
+    }
+    pub fn speed(mut self, speed: Speed) -> ChipEightBuilder {
+        self.speed = speed;
+        return self;
+    }
+    // Turbo when the host paces the frames itself.
+    pub fn pacing(mut self, pacing: Pacing) -> ChipEightBuilder {
+        self.pacing = pacing;
+        return self;
+    }
+    // Sixteen 4x5 hex digit sprites, five bytes each, from 0 to F.
+    pub fn font(mut self, font: [u8; 80]) -> ChipEightBuilder {
+        self.font = font;
+        return self;
+    }
+    pub fn seed(mut self, seed: u64) -> ChipEightBuilder {
+        self.seed = Some(seed);
+        return self;
+    }
+    pub fn random_mode(mut self, mode: RandomMode) -> ChipEightBuilder {
+        self.random_mode = mode;
+        return self;
+    }
+    pub fn display(mut self, display: Box<dyn Display>) -> ChipEightBuilder {
+        self.display = display;
+        return self;
+    }
+    pub fn audio(mut self, audio: Box<dyn Audio>) -> ChipEightBuilder {
+        self.audio = audio;
+        return self;
+    }
+    pub fn build(self) -> ChipEight {
+        let mut processor: Processor = Processor::new(
+            Memory::with_font(self.font),
+            Graphics::new(),
+            Keypad::new(),
+            Sound::new(),
+        );
+        processor.set_quirks(self.quirks);
+        let seed: u64 = self.seed.unwrap_or_else(random::entropy_seed);
+        processor.set_random_source(self.random_mode.source(seed));
+        let mut scheduler: Scheduler = Scheduler::new();
+        scheduler.set_speed(self.speed);
+        scheduler.set_pacing(self.pacing);
+        return ChipEight::new(processor, self.display, self.audio, scheduler, self.random_mode,
+            seed);
+    }
+}
+
+impl Default for ChipEightBuilder {
+    fn default() -> ChipEightBuilder {
+        return ChipEightBuilder::new();
+    }
+}
@@ -1,25 +1,366 @@
-use super::memory::Memory;
 use super::graphics::Graphics;
-use super::sound::Sound;
+use super::memory::Access;
 use super::processor::Processor;
-use super::keypad::Keypad;
-use super::api::Tick;
+use std::time::Instant;
 
-pub struct ChipEight { processor: Processor }
+use super::keypad::{Key, KeyEvent, Position};
+use super::quirks::Quirks;
+use super::random::{RandomMode, RandomSource};
+use super::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+use super::api::{Audio, Display, Frame, MachineState, Region, StepOutcome};
+use super::crash_report::CrashReport;
+use super::movie::Movie;
+use super::error::EmulatorError;
+use crate::common::hash_utils;
+use super::graphics::{WIDTH, HEIGHT};
+use super::scheduler::{Pacing, Scheduler, Speed};
+
+pub struct ChipEight {
+    processor: Processor,
+    display: Box<dyn Display>,
+    audio: Box<dyn Audio>,
+    // Whether the audio sink was last told to play.
+    playing: bool,
+    scheduler: Scheduler,
+    // Instructions left before the current 60 Hz frame ends.
+    frame_budget: u32,
+    frames: u64,
+    rom_sha1: Option<[u8; 20]>,
+    seed: u64,
+    random_mode: RandomMode,
+    recording: Option<Movie>,
+}
 
 impl ChipEight {
-    pub fn new() -> ChipEight {
+    // Use `ChipEightBuilder` to set one up.
+    pub(super) fn new(processor: Processor, display: Box<dyn Display>, audio: Box<dyn Audio>,
+            scheduler: Scheduler, random_mode: RandomMode, seed: u64) -> ChipEight {
         ChipEight {
-            processor: Processor::new(
-                Memory::new(),
-                Graphics::new(),
-                Keypad::new(),
-                Sound::new(),
-            ),
+            processor,
+            display,
+            audio,
+            playing: false,
+            scheduler,
+            frame_budget: 0,
+            frames: 0,
+            rom_sha1: None,
+            seed,
+            random_mode,
+            recording: None,
+        }
+    }
+    // Swap the display, e.g. when a debugger takes over the terminal.
+    pub fn set_display(&mut self, display: Box<dyn Display>) {
+        self.display = display;
+    }
+    // A ROM that does not load leaves the previous one, and its hash, in place.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), EmulatorError> {
+        self.processor.init(rom)?;
+        self.rom_sha1 = Some(hash_utils::sha1(rom));
+        return Ok(());
+    }
+    pub fn rom_sha1(&self) -> [u8; 20] {
+        return self.rom_sha1.unwrap_or([0; 20]);
+    }
+    // Capture the whole machine, quirks, random number generator and instruction budgets
+    // included.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer: StateWriter = StateWriter::new(&self.rom_sha1());
+        self.processor.save(&mut writer);
+        writer.put_u64(self.frames);
+        writer.put_u32(self.frame_budget);
+        writer.put_u64(self.seed);
+        writer.put_u8(self.random_mode.id());
+        self.scheduler.save(&mut writer);
+        return writer.finish();
+    }
+    // Restore a state saved while running the same ROM with the same random mode, the machine
+    // is left untouched if the state is rejected. A movie being recorded starts over from the
+    // restored state, as it has no way to describe the jump.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let backup: Vec<u8> = self.save_state();
+        let result: Result<(), SaveStateError> = self.restore(state);
+        if result.is_err() {
+            self.restore(&backup).expect("a freshly saved state always loads.");
+        } else if let Some(movie) = &self.recording {
+            self.recording = Some(Movie::record(self, movie.checksum_interval));
         }
+        return result;
+    }
+    // Record key changes from here on, for `movie::play` to reproduce later.
+    pub fn start_recording(&mut self, checksum_interval: u64) {
+        self.recording = Some(Movie::record(self, checksum_interval));
+    }
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        let mut movie: Movie = self.recording.take()?;
+        movie.finish(self);
+        return Some(movie);
+    }
+    fn restore(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader: StateReader = StateReader::new(state, &self.rom_sha1())?;
+        self.processor.load(&mut reader)?;
+        self.frames = reader.get_u64()?;
+        self.frame_budget = reader.get_u32()?;
+        self.seed = reader.get_u64()?;
+        if RandomMode::from_id(reader.get_u8()?) != Some(self.random_mode) {
+            return Err(SaveStateError::RandomModeMismatch);
+        }
+        self.scheduler.load(&mut reader)?;
+        return reader.finish();
+    }
+    pub fn crash_report(&self, error: EmulatorError) -> CrashReport {
+        let mut report: CrashReport = self.processor.crash_report(error);
+        report.rom_sha1 = self.rom_sha1;
+        return report;
+    }
+    // Reseed with one of the built in generators, making runs reproducible.
+    pub fn set_seed(&mut self, mode: RandomMode, seed: u64) {
+        self.seed = seed;
+        self.random_mode = mode;
+        self.processor.set_random_source(mode.source(seed));
+    }
+    // Bring your own generator, e.g. one replaying a fixed sequence. Movies still record the
+    // last built in generator, so they will not play back.
+    pub fn set_random_source(&mut self, random: Box<dyn RandomSource>) {
+        self.processor.set_random_source(random);
+    }
+    pub fn seed(&self) -> u64 {
+        return self.seed;
+    }
+    pub fn random_mode(&self) -> RandomMode {
+        return self.random_mode;
+    }
+    pub fn speed(&self) -> Speed {
+        return self.scheduler.speed();
+    }
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.scheduler.set_speed(speed);
+    }
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.scheduler.set_pacing(pacing);
+    }
+    // Execute a single instruction, finishing the frame once its instruction budget is spent. A
+    // frame with no budget at all goes by in a single step.
+    pub fn step(&mut self) -> Result<StepOutcome, EmulatorError> {
+        if self.frame_budget == 0 {
+            self.start_frame();
+        }
+        let outcome: StepOutcome = if self.frame_budget == 0 {
+            self.end_frame();
+            StepOutcome::Idle
+        } else {
+            let outcome: StepOutcome = self.processor.step()?;
+            self.frame_budget -= 1;
+            if self.frame_budget == 0 {
+                self.end_frame();
+            }
+            outcome
+        };
+        if self.sound_active() != self.playing {
+            self.playing = !self.playing;
+            self.audio.set_playing(self.playing);
+        }
+        return Ok(outcome);
+    }
+    // Run the rest of the current frame.
+    pub fn run_frame(&mut self) -> Result<(), EmulatorError> {
+        let frame: u64 = self.frames;
+        while self.frames == frame { self.step()?; }
+        return Ok(());
+    }
+    pub fn frames(&self) -> u64 {
+        return self.frames;
+    }
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.processor.set_quirks(quirks);
+    }
+    pub fn quirks(&self) -> Quirks {
+        return self.processor.quirks();
+    }
+    // Key changes wait in a queue until the next frame starts.
+    pub fn press_key(&mut self, key: Key) {
+        self.queue_key(key, Position::DOWN);
+    }
+    pub fn release_key(&mut self, key: Key) {
+        self.queue_key(key, Position::UP);
+    }
+    pub fn queue_key(&mut self, key: Key, position: Position) {
+        self.queue_event(KeyEvent { key, position, at: Instant::now() });
+    }
+    pub fn queue_event(&mut self, event: KeyEvent) {
+        self.processor.keypad_mut().queue(event);
+    }
+    pub fn is_key_down(&self, key: Key) -> bool {
+        return self.processor.keypad().is_down(key);
+    }
+    // Edges from the events applied at the start of the current frame.
+    pub fn just_pressed(&self, key: Key) -> bool {
+        return self.processor.keypad().just_pressed(key);
+    }
+    pub fn just_released(&self, key: Key) -> bool {
+        return self.processor.keypad().just_released(key);
+    }
+    pub fn is_waiting_for_key(&self) -> bool {
+        return self.processor.is_waiting();
+    }
+    pub fn is_waiting_for_frame(&self) -> bool {
+        return self.processor.is_waiting_for_frame();
+    }
+    pub fn program_counter(&self) -> u16 {
+        return self.processor.program_counter();
     }
-    pub fn run(mut self, rom: &Vec<u8>) {
-        self.processor.init(rom);
-        loop { self.processor.tick(); }
+    pub fn state(&self) -> MachineState<'_> {
+        return self.processor.state();
+    }
+    // Write straight into memory, anywhere from 0x000 to 0xFFF.
+    pub fn poke(&mut self, address: u16, bytes: &[u8]) -> Result<(), EmulatorError> {
+        return self.processor.poke(address, bytes);
+    }
+    // V0 to VF, by index.
+    pub fn set_register(&mut self, register: usize, value: u8) -> Result<(), EmulatorError> {
+        if register >= 16 {
+            return Err(EmulatorError::InvalidRegister { register });
+        }
+        self.processor.set_register_value(register, value);
+        return Ok(());
+    }
+    pub fn set_index_register(&mut self, value: u16) {
+        self.processor.set_index_register(value);
+    }
+    pub fn set_pc(&mut self, address: u16) {
+        self.processor.jump(address);
+    }
+    // Push a return address, as CALL would, failing if the stack is full.
+    pub fn push_stack(&mut self, address: u16) -> Result<(), EmulatorError> {
+        return self.processor.push_stack(address);
+    }
+    // Log the program's memory reads and writes, for watchpoints.
+    pub fn trace_memory(&mut self, on: bool) {
+        self.processor.memory_mut().trace_accesses(on);
+    }
+    // The accesses since the last call, empty while not tracing.
+    pub fn take_memory_accesses(&mut self) -> Vec<Access> {
+        return self.processor.memory_mut().take_accesses();
+    }
+    pub fn framebuffer(&self) -> Frame<'_> {
+        let everything: Region = Region { x: 0, y: 0, width: WIDTH, height: HEIGHT };
+        return self.processor.graphics().frame(everything);
+    }
+    // The buzzer sounds while the sound timer is running.
+    pub fn sound_active(&self) -> bool {
+        return self.processor.sound_timer() > 0;
+    }
+    // Show the frame without running it, e.g. after a rewind, still held to the frame rate.
+    pub fn show_frame(&mut self) {
+        self.present();
+        self.scheduler.wait_for_next_frame();
+    }
+    fn start_frame(&mut self) {
+        self.frame_budget = self.scheduler.frame_budget();
+        let events: Vec<KeyEvent> = self.processor.apply_key_events();
+        if let Some(movie) = &mut self.recording {
+            for event in events {
+                movie.key_changed(self.frames, event.key, event.position);
+            }
+        }
+    }
+    fn end_frame(&mut self) {
+        self.processor.tick_timers();
+        self.present();
+        self.frames += 1;
+        if let Some(mut movie) = self.recording.take() {
+            movie.frame_ended(self);
+            self.recording = Some(movie);
+        }
+        self.scheduler.wait_for_next_frame();
+    }
+    // Push the framebuffer to the display if anything was drawn since the last frame.
+    fn present(&mut self) {
+        let graphics: &mut Graphics = self.processor.graphics_mut();
+        if let Some(dirty) = graphics.take_dirty() {
+            self.display.present(&graphics.frame(dirty));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::builder::ChipEightBuilder;
+
+    fn machine(mode: RandomMode) -> ChipEight {
+        let mut chip_eight: ChipEight = ChipEightBuilder::new()
+            .instructions_per_second(700).pacing(Pacing::Turbo).random_mode(mode).seed(1).build();
+        // 1NNN back to itself forever.
+        chip_eight.load_rom(&[0x12, 0x00]).unwrap();
+        return chip_eight;
+    }
+
+    fn budgets(chip_eight: &mut ChipEight, frames: usize) -> Vec<u64> {
+        let mut budgets: Vec<u64> = Vec::new();
+        for _ in 0..frames {
+            let mut steps: u64 = 0;
+            let start: u64 = chip_eight.frames();
+            while chip_eight.frames() == start {
+                chip_eight.step().unwrap();
+                steps += 1;
+            }
+            budgets.push(steps);
+        }
+        return budgets;
+    }
+
+    #[test]
+    fn restored_states_keep_the_instruction_budgets() {
+        let mut original: ChipEight = machine(RandomMode::Xorshift);
+        original.run_frame().unwrap();
+        let state: Vec<u8> = original.save_state();
+        let expected: Vec<u64> = budgets(&mut original, 5);
+
+        let mut restored: ChipEight = machine(RandomMode::Xorshift);
+        restored.load_state(&state).unwrap();
+        assert_eq!(budgets(&mut restored, 5), expected);
+    }
+
+    #[test]
+    fn states_from_another_random_mode_are_rejected() {
+        let state: Vec<u8> = machine(RandomMode::Table).save_state();
+        let mut chip_eight: ChipEight = machine(RandomMode::Xorshift);
+        let before: Vec<u8> = chip_eight.save_state();
+        assert_eq!(chip_eight.load_state(&state), Err(SaveStateError::RandomModeMismatch));
+        assert_eq!(chip_eight.save_state(), before);
+    }
+
+    #[test]
+    fn states_are_version_one() {
+        let mut state: Vec<u8> = machine(RandomMode::Xorshift).save_state();
+        assert_eq!(&state[..5], b"C8SS\x01");
+        state[4] = 2;
+        assert_eq!(machine(RandomMode::Xorshift).load_state(&state),
+            Err(SaveStateError::UnsupportedVersion(2)));
+    }
+
+    #[test]
+    fn setting_a_register_past_vf_is_an_error() {
+        let mut chip_eight: ChipEight = machine(RandomMode::Xorshift);
+        assert_eq!(chip_eight.set_register(0xF, 7), Ok(()));
+        assert_eq!(chip_eight.state().registers[0xF], 7);
+        assert_eq!(chip_eight.set_register(16, 7),
+            Err(EmulatorError::InvalidRegister { register: 16 }));
+    }
+
+    #[test]
+    fn reloading_replaces_the_whole_rom_and_its_hash() {
+        let mut chip_eight: ChipEight = machine(RandomMode::Xorshift);
+        chip_eight.load_rom(&[0xA1, 0xA2, 0xA3, 0xA4]).unwrap();
+        chip_eight.load_rom(&[0xB1, 0xB2]).unwrap();
+        assert_eq!(chip_eight.state().memory[0x200..0x204], [0xB1, 0xB2, 0, 0]);
+        assert_eq!(chip_eight.rom_sha1(), hash_utils::sha1(&[0xB1, 0xB2]));
+
+        let too_large: Vec<u8> = vec![0xC1; 0x1000];
+        assert_eq!(chip_eight.load_rom(&too_large),
+            Err(EmulatorError::RomTooLarge { size: 0x1000 }));
+        assert_eq!(chip_eight.state().memory[0x200..0x204], [0xB1, 0xB2, 0, 0]);
+        assert_eq!(chip_eight.rom_sha1(), hash_utils::sha1(&[0xB1, 0xB2]));
     }
 }
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::fs;
+use std::io::Error;
+
+use crate::common::hash_utils;
+
+use super::error::EmulatorError;
+use super::instruction;
+
+// Instructions shown either side of the faulting one.
+const DISASSEMBLY_RADIUS: usize = 6;
+// Bytes shown either side of I.
+const HEX_DUMP_RADIUS: usize = 32;
+const HEX_DUMP_WIDTH: usize = 16;
+
+// Everything needed to make sense of a fault after the fact.
+pub struct CrashReport {
+    pub error: EmulatorError,
+    pub registers: [u8; 16],
+    pub index_register: u16,
+    pub program_counter: u16,
+    // Return addresses, bottom of the stack first.
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    // (address, opcode) of the most recently executed instructions, oldest first.
+    pub history: Vec<(u16, u16)>,
+    pub memory: Vec<u8>,
+    pub rom_sha1: Option<[u8; 20]>,
+}
+
+impl CrashReport {
+    // Print the report to stderr, and also save it to `path` when given.
+    pub fn write(&self, path: Option<&str>) -> Result<(), Error> {
+        let text: String = self.to_string();
+        eprint!("{}", text);
+        if let Some(path) = path {
+            fs::write(path, &text)?;
+        }
+        return Ok(());
+    }
+
+    // Where the fault happened, falling back on the PC for faults without an address.
+    fn fault_address(&self) -> u16 {
+        return match self.error {
+            EmulatorError::InvalidOpcode { pc, .. }
+            | EmulatorError::StackOverflow { pc }
+            | EmulatorError::StackUnderflow { pc }
+            | EmulatorError::PcOutOfRange { pc } => pc,
+            _ => self.program_counter,
+        };
+    }
+
+    fn opcode_at(&self, address: usize) -> Option<u16> {
+        let bytes: &[u8] = self.memory.get(address..address + 2)?;
+        return Some(u16::from_be_bytes([bytes[0], bytes[1]]));
+    }
+}
+
+fn describe(opcode: u16) -> String {
+    return match instruction::decode(opcode) {
+        Ok(instruction) => instruction.to_string(),
+        Err(_) => "???".to_string(),
+    };
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "=== CHIP-8 crash report ===")?;
+        writeln!(f, "Fault: {}", self.error)?;
+        match self.rom_sha1 {
+            Some(digest) => writeln!(f, "ROM SHA-1: {}", hash_utils::to_hex(&digest))?,
+            None => writeln!(f, "ROM SHA-1: unknown")?,
+        }
+
+        writeln!(f, "\nRegisters:")?;
+        for row in self.registers.chunks(8).enumerate() {
+            let (offset, values) = row;
+            let line: Vec<String> = values.iter().enumerate()
+                .map(|(i, value)| format!("V{:X}={:02X}", offset * 8 + i, value))
+                .collect();
+            writeln!(f, "  {}", line.join(" "))?;
+        }
+        writeln!(f, "  I={:04X} PC={:04X} SP={}", self.index_register, self.program_counter,
+            self.stack.len())?;
+        writeln!(f, "  DT={:02X} ST={:02X}", self.delay_timer, self.sound_timer)?;
+
+        writeln!(f, "\nStack (top first):")?;
+        if self.stack.is_empty() {
+            writeln!(f, "  (empty)")?;
+        }
+        for (level, address) in self.stack.iter().enumerate().rev() {
+            writeln!(f, "  {:>2}: {:03X}", level, address)?;
+        }
+
+        writeln!(f, "\nLast {} instructions (oldest first):", self.history.len())?;
+        for (address, opcode) in &self.history {
+            writeln!(f, "  {:03X}: {:04X}  {}", address, opcode, describe(*opcode))?;
+        }
+
+        let fault: usize = self.fault_address() as usize;
+        writeln!(f, "\nDisassembly around {:03X}:", fault)?;
+        let first: usize = fault.saturating_sub(DISASSEMBLY_RADIUS * 2);
+        for address in (first..=fault + DISASSEMBLY_RADIUS * 2).step_by(2) {
+            let Some(opcode) = self.opcode_at(address) else { break; };
+            let marker: &str = if address == fault {">"} else {" "};
+            writeln!(f, "{} {:03X}: {:04X}  {}", marker, address, opcode, describe(opcode))?;
+        }
+
+        let index: usize = self.index_register as usize;
+        writeln!(f, "\nMemory around I={:03X}:", index)?;
+        let start: usize = index.saturating_sub(HEX_DUMP_RADIUS) / HEX_DUMP_WIDTH * HEX_DUMP_WIDTH;
+        let end: usize = (index + HEX_DUMP_RADIUS).min(self.memory.len());
+        for row_start in (start..end).step_by(HEX_DUMP_WIDTH) {
+            let row: &[u8] = &self.memory[row_start..(row_start + HEX_DUMP_WIDTH).min(end)];
+            let hex: Vec<String> = row.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = row.iter()
+                .map(|byte| if byte.is_ascii_graphic() {*byte as char} else {'.'})
+                .collect();
+            writeln!(f, "  {:03X}: {:<47}  |{}|", row_start, hex.join(" "), text)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_every_section() {
+        let mut memory: Vec<u8> = vec![0; 16];
+        memory[..8].copy_from_slice(&[0x22, 0x04, 0x60, 0x01, 0x00, 0xEE, 0x41, 0x42]);
+        let mut registers: [u8; 16] = [0; 16];
+        registers[0] = 1;
+        registers[0xF] = 0xFF;
+        let report: CrashReport = CrashReport {
+            error: EmulatorError::StackUnderflow { pc: 0x004 },
+            registers,
+            index_register: 0x006,
+            program_counter: 0x006,
+            stack: vec![0x002],
+            delay_timer: 2,
+            sound_timer: 0,
+            history: vec![(0x002, 0x6001), (0x004, 0x00EE)],
+            memory,
+            rom_sha1: Some(hash_utils::sha1(b"abc")),
+        };
+        assert_eq!(report.to_string(), "\
+=== CHIP-8 crash report ===
+Fault: stack underflow at 004
+ROM SHA-1: a9993e364706816aba3e25717850c26c9cd0d89d
+
+Registers:
+  V0=01 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00
+  V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=FF
+  I=0006 PC=0006 SP=1
+  DT=02 ST=00
+
+Stack (top first):
+   0: 002
+
+Last 2 instructions (oldest first):
+  002: 6001  LD V0, 0x01
+  004: 00EE  RET
+
+Disassembly around 004:
+  000: 2204  CALL 0x204
+  002: 6001  LD V0, 0x01
+> 004: 00EE  RET
+  006: 4142  SNE V1, 0x42
+  008: 0000  SYS 0x000
+  00A: 0000  SYS 0x000
+  00C: 0000  SYS 0x000
+  00E: 0000  SYS 0x000
+
+Memory around I=006:
+  000: 22 04 60 01 00 EE 41 42 00 00 00 00 00 00 00 00  |\".`...AB........|
+");
+    }
+}
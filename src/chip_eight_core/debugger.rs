@@ -0,0 +1,354 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::api::{MachineState, StepOutcome};
+use super::chip_eight::ChipEight;
+use super::error::EmulatorError;
+use super::expression::{Context, Expression, ExpressionError, Template};
+use super::instruction::{self, Instruction};
+use super::memory::{Access, AccessKind};
+
+// How far to run before handing control back, breakpoints and faults aside.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunUntil {
+    Steps(u32),
+    // One instruction, or a whole subroutine if it is a CALL.
+    StepOver,
+    // Out of the current subroutine, through its RET.
+    Finish,
+    Frames(u32),
+    // Until something else stops it.
+    Forever,
+}
+
+// Something to keep an eye on while the program runs. Ranges include both ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Watch {
+    Write { start: u16, end: u16 },
+    Read { start: u16, end: u16 },
+    // The register changing to this value.
+    Register { register: usize, value: u8 },
+    // I changing to an address in the range.
+    Index { start: u16, end: u16 },
+}
+
+fn range(start: u16, end: u16) -> String {
+    return if start == end {
+        format!("0x{:03X}", start)
+    } else {
+        format!("0x{:03X}-0x{:03X}", start, end)
+    };
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Watch::Write { start, end } => write!(f, "write to {}", range(start, end)),
+            Watch::Read { start, end } => write!(f, "read of {}", range(start, end)),
+            Watch::Register { register, value } =>
+                write!(f, "V{:X} becoming 0x{:02X}", register, value),
+            Watch::Index { start, end } => write!(f, "I moving into {}", range(start, end)),
+        }
+    }
+}
+
+// A watchpoint going off, and the instruction that set it off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub number: u32,
+    pub watch: Watch,
+    pub pc: u16,
+    pub instruction: Instruction,
+    // The first address read or written in the range, or the new value of the register or I.
+    pub value: u16,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "watchpoint {}: ", self.number)?;
+        match self.watch {
+            Watch::Write { .. } => write!(f, "wrote 0x{:03X}", self.value)?,
+            Watch::Read { .. } => write!(f, "read 0x{:03X}", self.value)?,
+            Watch::Register { register, .. } =>
+                write!(f, "V{:X} became 0x{:02X}", register, self.value)?,
+            Watch::Index { .. } => write!(f, "I became 0x{:03X}", self.value)?,
+        }
+        write!(f, " at 0x{:03X}: {}", self.pc, self.instruction)
+    }
+}
+
+// Where to stop, or what to print, when the PC gets to an address.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Breakpoint {
+    // Only stop, or print, when this comes out true.
+    pub condition: Option<Expression>,
+    // Print this and carry on, rather than stopping.
+    pub log: Option<Template>,
+    // Times the PC has got there, whether the condition held or not.
+    pub hits: u64,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(condition) = &self.condition {
+            write!(f, "if {} ", condition)?;
+        }
+        if let Some(log) = &self.log {
+            write!(f, "log \"{}\" ", log)?;
+        }
+        write!(f, "(hit {} time{})", self.hits, if self.hits == 1 {""} else {"s"})
+    }
+}
+
+// Why the machine stopped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Done,
+    Breakpoint { address: u16 },
+    Watchpoint(WatchHit),
+    Fault(EmulatorError),
+    // A breakpoint condition that could not be worked out, e.g. reading outside memory.
+    Condition { address: u16, error: ExpressionError },
+    // Asked to stop from outside, e.g. the user pressed a key.
+    Interrupted,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Done => write!(f, "stopped"),
+            Stop::Breakpoint { address } => write!(f, "breakpoint at 0x{:03X}", address),
+            Stop::Watchpoint(hit) => write!(f, "{}", hit),
+            Stop::Fault(error) => write!(f, "fault: {}", error),
+            Stop::Condition { address, error } =>
+                write!(f, "breakpoint at 0x{:03X}, condition failed: {}", address, error),
+            Stop::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+// Whoever is driving the debugger, told about things while the machine runs.
+pub trait DebugHost {
+    // Checked at every frame boundary, true stops the run.
+    fn interrupted(&mut self) -> bool;
+    // A logpoint at `address` went off.
+    fn log(&mut self, address: u16, message: &str) -> ();
+}
+
+// Breakpoints, watchpoints and run control over a machine that is paused in between.
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    // By the number they are listed and deleted by.
+    watchpoints: BTreeMap<u32, Watch>,
+    next_watchpoint: u32,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger { breakpoints: BTreeMap::new(), watchpoints: BTreeMap::new(), next_watchpoint: 1 }
+    }
+    // Returns the number to refer to it by.
+    pub fn add_watchpoint(&mut self, watch: Watch) -> Result<u32, EmulatorError> {
+        if let Watch::Register { register: register @ 16.., .. } = watch {
+            return Err(EmulatorError::InvalidRegister { register });
+        }
+        let number: u32 = self.next_watchpoint;
+        self.next_watchpoint += 1;
+        self.watchpoints.insert(number, watch);
+        return Ok(number);
+    }
+    pub fn remove_watchpoint(&mut self, number: u32) -> bool {
+        return self.watchpoints.remove(&number).is_some();
+    }
+    pub fn watchpoints(&self) -> impl Iterator<Item = (u32, Watch)> + '_ {
+        return self.watchpoints.iter().map(|(number, watch)| (*number, *watch));
+    }
+    // A plain breakpoint, returns false if there already was one there.
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        if self.breakpoints.contains_key(&address) { return false; }
+        self.breakpoints.insert(address, Breakpoint::default());
+        return true;
+    }
+    // Returns the one it replaced.
+    pub fn set_breakpoint(&mut self, address: u16, breakpoint: Breakpoint) -> Option<Breakpoint> {
+        return self.breakpoints.insert(address, breakpoint);
+    }
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        return self.breakpoints.remove(&address).is_some();
+    }
+    pub fn breakpoints(&self) -> impl Iterator<Item = (u16, &Breakpoint)> + '_ {
+        return self.breakpoints.iter().map(|(address, breakpoint)| (*address, breakpoint));
+    }
+    pub fn is_breakpoint(&self, address: u16) -> bool {
+        return self.breakpoints.contains_key(&address);
+    }
+    // Run from wherever the machine is paused. Breakpoints only count when an instruction has
+    // just brought the PC to them, so carrying on from one works, even while the CPU is held up
+    // by FX0A or the display wait.
+    pub fn run(&mut self, chip_eight: &mut ChipEight, until: RunUntil,
+            host: &mut dyn DebugHost) -> Stop {
+        // Memory is only traced while there is a watchpoint on it, and while running.
+        let tracing: bool = self.watchpoints.values()
+            .any(|watch| matches!(watch, Watch::Write { .. } | Watch::Read { .. }));
+        chip_eight.trace_memory(tracing);
+        let stop: Stop = self.run_traced(chip_eight, until, host);
+        chip_eight.trace_memory(false);
+        return stop;
+    }
+    fn run_traced(&mut self, chip_eight: &mut ChipEight, until: RunUntil,
+            host: &mut dyn DebugHost) -> Stop {
+        let depth: usize = chip_eight.state().stack_pointer;
+        let start_frame: u64 = chip_eight.frames();
+        // Instructions executed, steps held up by the display wait do not count.
+        let mut steps: u32 = 0;
+        // Whether the last step executed an instruction, and so got the PC where it is.
+        let mut arrived: bool = false;
+        loop {
+            let address: u16 = chip_eight.program_counter();
+            if arrived {
+                if let Some(stop) = self.check_breakpoint(chip_eight, address, host) {
+                    return stop;
+                }
+            }
+            let frame: u64 = chip_eight.frames();
+            let before: MachineState = chip_eight.state();
+            let (registers, index): ([u8; 16], u16) = (before.registers, before.index_register);
+            let outcome: StepOutcome = match chip_eight.step() {
+                Ok(outcome) => outcome,
+                Err(error) => return Stop::Fault(error),
+            };
+            arrived = matches!(outcome, StepOutcome::Executed { .. });
+            if let StepOutcome::Executed { pc, instruction } = outcome {
+                steps += 1;
+                let accesses: Vec<Access> = chip_eight.take_memory_accesses();
+                let state: MachineState = chip_eight.state();
+                for (number, watch) in &self.watchpoints {
+                    let value: Option<u16> = match *watch {
+                        Watch::Write { start, end } =>
+                            touched(&accesses, AccessKind::Write, start, end),
+                        Watch::Read { start, end } =>
+                            touched(&accesses, AccessKind::Read, start, end),
+                        Watch::Register { register, value } => {
+                            let now: u8 = state.registers[register];
+                            (now == value && registers[register] != value).then_some(now as u16)
+                        }
+                        Watch::Index { start, end } => {
+                            let now: u16 = state.index_register;
+                            (now != index && (start..=end).contains(&now)).then_some(now)
+                        }
+                    };
+                    if let Some(value) = value {
+                        let watch: Watch = *watch;
+                        return Stop::Watchpoint(WatchHit {
+                            number: *number, watch, pc, instruction, value,
+                        });
+                    }
+                }
+            }
+
+            // Nothing more happens until a key is pressed, so stepping stops there.
+            let waiting: bool = outcome == StepOutcome::WaitingForKey;
+            let finished: bool = match until {
+                RunUntil::Steps(count) => waiting || steps >= count,
+                // Back at the same depth once the subroutine the CALL went into returns.
+                RunUntil::StepOver => {
+                    waiting || (steps > 0 && chip_eight.state().stack_pointer <= depth)
+                }
+                RunUntil::Finish => chip_eight.state().stack_pointer < depth,
+                RunUntil::Frames(count) => chip_eight.frames() - start_frame >= count as u64,
+                RunUntil::Forever => false,
+            };
+            if finished { return Stop::Done; }
+            if chip_eight.frames() != frame && host.interrupted() {
+                return Stop::Interrupted;
+            }
+        }
+    }
+    // Count a hit on any breakpoint at `address` and see whether it stops the run.
+    fn check_breakpoint(&mut self, chip_eight: &ChipEight, address: u16,
+            host: &mut dyn DebugHost) -> Option<Stop> {
+        let breakpoint: &mut Breakpoint = self.breakpoints.get_mut(&address)?;
+        breakpoint.hits += 1;
+        let state: MachineState = chip_eight.state();
+        let context: Context = Context { state: &state, hits: breakpoint.hits };
+        if let Some(condition) = &breakpoint.condition {
+            match condition.evaluate(&context) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(error) => return Some(Stop::Condition { address, error }),
+            }
+        }
+        return match &breakpoint.log {
+            Some(log) => {
+                host.log(address, &log.render(&context));
+                None
+            }
+            None => Some(Stop::Breakpoint { address }),
+        };
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        return Debugger::new();
+    }
+}
+
+// The first address in `start..=end` an access of this kind touched.
+fn touched(accesses: &[Access], kind: AccessKind, start: u16, end: u16) -> Option<u16> {
+    return accesses.iter()
+        .filter(|access| access.kind == kind && access.length > 0)
+        .map(|access| (access.address.max(start),
+            access.address as u32 + access.length as u32 - 1))
+        .find(|(first, last)| *first as u32 <= *last && *first <= end)
+        .map(|(first, _)| first);
+}
+
+// The instruction at `address` as it stands in memory, "???" when it does not decode.
+pub fn describe_at(chip_eight: &ChipEight, address: u16) -> String {
+    return match chip_eight.state().opcode_at(address) {
+        Some(opcode) => match instruction::decode(opcode) {
+            Ok(instruction) => format!("{:04X}  {}", opcode, instruction),
+            Err(_) => format!("{:04X}  ???", opcode),
+        },
+        None => "outside memory".to_string(),
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::builder::ChipEightBuilder;
+
+    struct Host;
+
+    impl DebugHost for Host {
+        fn interrupted(&mut self) -> bool {
+            return false;
+        }
+        fn log(&mut self, _address: u16, _message: &str) -> () {}
+    }
+
+    #[test]
+    fn running_an_instruction_reads_it() {
+        let mut chip_eight: ChipEight = ChipEightBuilder::new().build();
+        // V0 = 5, then jump to self.
+        chip_eight.load_rom(&[0x60, 0x05, 0x12, 0x02]).unwrap();
+        let mut debugger: Debugger = Debugger::new();
+        let watch: Watch = Watch::Read { start: 0x202, end: 0x203 };
+        assert_eq!(debugger.add_watchpoint(watch), Ok(1));
+        let stop: Stop = debugger.run(&mut chip_eight, RunUntil::Steps(10), &mut Host);
+        let Stop::Watchpoint(hit) = stop else { panic!("expected a watchpoint, got {}", stop) };
+        assert_eq!((hit.pc, hit.value), (0x202, 0x202));
+    }
+
+    #[test]
+    fn watching_a_register_past_vf_is_an_error() {
+        let mut debugger: Debugger = Debugger::new();
+        let watch: Watch = Watch::Register { register: 16, value: 0 };
+        assert_eq!(debugger.add_watchpoint(watch),
+            Err(EmulatorError::InvalidRegister { register: 16 }));
+        assert_eq!(debugger.watchpoints().count(), 0);
+    }
+}
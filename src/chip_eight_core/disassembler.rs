@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::instruction::{self, Instruction};
+use super::memory::PROGRAM_START;
+
+// Data bytes are grouped onto lines of at most this many.
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    // None when the bytes are data rather than code.
+    pub instruction: Option<Instruction>,
+}
+
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    // Names given to the addresses the code refers to.
+    pub labels: BTreeMap<u16, String>,
+    // Code that starts inside an instruction already on a line, e.g. after a jump into its
+    // second byte. Rendered as comments below the line it overlaps.
+    pub overlapping: Vec<Line>,
+}
+
+// Decode every pair of bytes from the start of the ROM, whatever they turn out to be.
+pub fn linear(rom: &[u8]) -> Disassembly {
+    let mut lines: Vec<Line> = Vec::new();
+    for (offset, chunk) in rom.chunks(2).enumerate() {
+        let address: u16 = PROGRAM_START as u16 + offset as u16 * 2;
+        let instruction: Option<Instruction> = match chunk {
+            [high, low] => instruction::decode(u16::from_be_bytes([*high, *low])).ok(),
+            _ => None,
+        };
+        lines.push(Line { address, bytes: chunk.to_vec(), instruction });
+    }
+    return Disassembly { lines, labels: BTreeMap::new(), overlapping: Vec::new() };
+}
+
+// Follow every jump, call and skip reachable from the entry point, so whatever is never
+// executed is left as data. Targets of computed jumps (BNNN) cannot be followed.
+pub fn recursive(rom: &[u8]) -> Disassembly {
+    let start: usize = PROGRAM_START;
+    let end: usize = start + rom.len();
+    let fetch = |address: usize| -> Option<Instruction> {
+        if address < start || address + 1 >= end { return None; }
+        let offset: usize = address - start;
+        return instruction::decode(u16::from_be_bytes([rom[offset], rom[offset + 1]])).ok();
+    };
+
+    let mut code: BTreeMap<u16, Instruction> = BTreeMap::new();
+    let mut labels: BTreeMap<u16, String> = BTreeMap::new();
+    let mut pending: Vec<usize> = vec![start];
+    while let Some(address) = pending.pop() {
+        if code.contains_key(&(address as u16)) { continue; }
+        let Some(instruction) = fetch(address) else { continue; };
+        code.insert(address as u16, instruction);
+
+        let next: usize = address + 2;
+        match instruction {
+            Instruction::Ret | Instruction::JpV0(_) => {}
+            Instruction::Jp(target) => {
+                labels.entry(target).or_insert_with(|| format!("label_{:03X}", target));
+                pending.push(target as usize);
+            }
+            Instruction::Call(target) => {
+                labels.insert(target, format!("sub_{:03X}", target));
+                pending.push(target as usize);
+                pending.push(next);
+            }
+            Instruction::SeVxByte { .. }
+            | Instruction::SneVxByte { .. }
+            | Instruction::SeVxVy { .. }
+            | Instruction::SneVxVy { .. }
+            | Instruction::Skp { .. }
+            | Instruction::Sknp { .. } => {
+                pending.push(next);
+                pending.push(next + 2);
+            }
+            _ => pending.push(next),
+        }
+        if let Instruction::LdI(target) = instruction {
+            labels.entry(target).or_insert_with(|| format!("data_{:03X}", target));
+        }
+    }
+
+    // Bytes covered by decoded instructions, anything else is emitted as data.
+    let covered: BTreeSet<usize> = code.keys()
+        .flat_map(|address| [*address as usize, *address as usize + 1])
+        .collect();
+    let mut lines: Vec<Line> = Vec::new();
+    let mut address: usize = start;
+    while address < end {
+        if let Some(instruction) = code.get(&(address as u16)) {
+            let offset: usize = address - start;
+            lines.push(Line {
+                address: address as u16,
+                bytes: rom[offset..offset + 2].to_vec(),
+                instruction: Some(*instruction),
+            });
+            address += 2;
+            continue;
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        let data_start: usize = address;
+        while address < end && !covered.contains(&address) && bytes.len() < DATA_BYTES_PER_LINE
+            && (bytes.is_empty() || !labels.contains_key(&(address as u16))) {
+            bytes.push(rom[address - start]);
+            address += 1;
+        }
+        if bytes.is_empty() {
+            // The second half of an instruction that overlaps one decoded elsewhere.
+            bytes.push(rom[address - start]);
+            address += 1;
+        }
+        lines.push(Line { address: data_start as u16, bytes, instruction: None });
+    }
+    let starts: BTreeSet<u16> = lines.iter().map(|line| line.address).collect();
+    let overlapping: Vec<Line> = code.iter()
+        .filter(|(address, _)| !starts.contains(*address))
+        .map(|(address, instruction)| {
+            let offset: usize = *address as usize - start;
+            return Line {
+                address: *address,
+                bytes: rom[offset..offset + 2].to_vec(),
+                instruction: Some(*instruction),
+            };
+        })
+        .collect();
+    // Only keep labels that point somewhere in the listing.
+    labels.retain(|address, _| starts.contains(address) || code.contains_key(address));
+    return Disassembly { lines, labels, overlapping };
+}
+
+impl Disassembly {
+    // Address, raw bytes and mnemonic for every line, with a label line before each target.
+    pub fn render(&self) -> String {
+        let name = |address: u16| -> String {
+            return self.labels.get(&address).cloned()
+                .unwrap_or_else(|| format!("0x{:03X}", address));
+        };
+        let text = |line: &Line| -> String {
+            let raw: String = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = match &line.instruction {
+                Some(instruction) => instruction.mnemonic(&name),
+                None => {
+                    let values: Vec<String> =
+                        line.bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                    format!("db {}", values.join(", "))
+                }
+            };
+            return format!("{:03X}: {:<4}  {}", line.address, raw, text);
+        };
+        let mut output: String = String::new();
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.address) {
+                let _ = writeln!(output, "{}:", label);
+            }
+            let _ = writeln!(output, "{}", text(line));
+            let inside = line.address + 1..line.address + line.bytes.len() as u16;
+            let overlaps = self.overlapping.iter()
+                .filter(|overlap| inside.contains(&overlap.address));
+            for overlap in overlaps {
+                if let Some(label) = self.labels.get(&overlap.address) {
+                    let _ = writeln!(output, "; {}:", label);
+                }
+                let _ = writeln!(output, "; {}  (overlaps the line above)", text(overlap));
+            }
+        }
+        return output;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Jumps back into the middle of the LD, whose second byte starts a JP of its own.
+    const ROM: [u8; 8] = [0x12, 0x04, 0x00, 0xE0, 0x60, 0x12, 0x12, 0x05];
+
+    #[test]
+    fn linear_decodes_every_pair() {
+        assert_eq!(linear(&ROM).render(), "\
+200: 1204  JP 0x204
+202: 00E0  CLS
+204: 6012  LD V0, 0x12
+206: 1205  JP 0x205
+");
+    }
+
+    #[test]
+    fn recursive_follows_the_code() {
+        let disassembly: Disassembly = recursive(&ROM);
+        assert_eq!(disassembly.overlapping, [Line {
+            address: 0x205,
+            bytes: vec![0x12, 0x12],
+            instruction: Some(Instruction::Jp(0x212)),
+        }]);
+        assert_eq!(disassembly.render(), "\
+200: 1204  JP label_204
+202: 00E0  db 0x00, 0xE0
+label_204:
+204: 6012  LD V0, 0x12
+; label_205:
+; 205: 1212  JP 0x212  (overlaps the line above)
+206: 1205  JP label_205
+");
+    }
+
+    #[test]
+    fn recursive_leaves_an_odd_byte_as_data() {
+        let disassembly: Disassembly = recursive(&[0x00, 0xE0, 0x00]);
+        assert_eq!(disassembly.render(), "200: 00E0  CLS\n202: 00    db 0x00\n");
+    }
+}
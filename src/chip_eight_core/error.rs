@@ -0,0 +1,39 @@
+use std::fmt;
+
+// Everything a misbehaving ROM can do to the machine. Frontends get these back from stepping
+// instead of the process aborting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmulatorError {
+    InvalidOpcode { pc: u16, opcode: u16 },
+    // A 17th nested CALL.
+    StackOverflow { pc: u16 },
+    // RET with nothing on the stack.
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { addr: usize },
+    // The program counter ran off the end of memory.
+    PcOutOfRange { pc: u16 },
+    RomTooLarge { size: usize },
+    // A frontend asked for a register past VF.
+    InvalidRegister { register: usize },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulatorError::InvalidOpcode { pc, opcode } =>
+                write!(f, "invalid opcode {:04X} at {:03X}", opcode, pc),
+            EmulatorError::StackOverflow { pc } => write!(f, "stack overflow at {:03X}", pc),
+            EmulatorError::StackUnderflow { pc } => write!(f, "stack underflow at {:03X}", pc),
+            EmulatorError::MemoryOutOfBounds { addr } =>
+                write!(f, "memory access out of bounds at {:04X}", addr),
+            EmulatorError::PcOutOfRange { pc } =>
+                write!(f, "program counter out of range: {:04X}", pc),
+            EmulatorError::RomTooLarge { size } =>
+                write!(f, "rom of {} bytes does not fit in memory", size),
+            EmulatorError::InvalidRegister { register } =>
+                write!(f, "there is no register V{:X}", register),
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {}
@@ -0,0 +1,564 @@
+use std::fmt;
+
+use super::api::MachineState;
+
+// Expressions over the machine state, for conditional breakpoints and logpoints:
+//
+//   v0 to vf, i, pc, sp, dt, st   registers and timers
+//   hits                          times the breakpoint has been reached, this time included
+//   [addr]  w[addr]               the byte, or big-endian word, at addr
+//   + - * / %  & | ^ ~  << >>     arithmetic and bitwise operators
+//   == != < <= > >=  && || !      comparisons and logic, giving 1 or 0
+//
+// Numbers are decimal, or hex with 0x. Everything is a 64-bit signed integer, and anything
+// other than 0 is true.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpressionError {
+    pub message: String,
+    // Where in the text it went wrong, for errors found while parsing.
+    pub column: Option<usize>,
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "column {}: {}", column + 1, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+fn error(message: String, column: Option<usize>) -> ExpressionError {
+    return ExpressionError { message, column };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Variable {
+    Register(usize),
+    Index,
+    ProgramCounter,
+    StackPointer,
+    DelayTimer,
+    SoundTimer,
+    Hits,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl Operator {
+    // Binding strength, loosest first, as in C.
+    fn precedence(self) -> u8 {
+        return match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::BitOr => 3,
+            Operator::BitXor => 4,
+            Operator::BitAnd => 5,
+            Operator::Equal | Operator::NotEqual => 6,
+            Operator::Less | Operator::LessEqual | Operator::Greater | Operator::GreaterEqual => 7,
+            Operator::ShiftLeft | Operator::ShiftRight => 8,
+            Operator::Add | Operator::Subtract => 9,
+            Operator::Multiply | Operator::Divide | Operator::Remainder => 10,
+        };
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Variable(Variable),
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Negate(Box<Node>),
+    Not(Box<Node>),
+    Complement(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// Longest first, so `<=` is not read as `<` then `=`.
+const SYMBOLS: [&str; 24] = [
+    "<<", ">>", "==", "!=", "<=", ">=", "&&", "||",
+    "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "(", ")", "[", "]",
+];
+
+// (token, column it starts at)
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, ExpressionError> {
+    let mut tokens: Vec<(Token, usize)> = Vec::new();
+    let mut column: usize = 0;
+    while column < text.len() {
+        let rest: &str = &text[column..];
+        let character: char = rest.chars().next().unwrap();
+        if character.is_whitespace() {
+            column += character.len_utf8();
+        } else if character.is_ascii_alphanumeric() || character == '_' {
+            let length: usize = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word: &str = &rest[..length];
+            let token: Token = if character.is_ascii_digit() {
+                let number: Option<i64> = match word.strip_prefix("0x")
+                        .or_else(|| word.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16).ok(),
+                    None => word.parse().ok(),
+                };
+                Token::Number(number
+                    .ok_or_else(|| error(format!("bad number '{}'", word), Some(column)))?)
+            } else {
+                Token::Name(word.to_ascii_lowercase())
+            };
+            tokens.push((token, column));
+            column += length;
+        } else {
+            let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) else {
+                return Err(error(format!("unexpected '{}'", character), Some(column)));
+            };
+            tokens.push((Token::Symbol(symbol), column));
+            column += symbol.len();
+        }
+    }
+    return Ok(tokens);
+}
+
+// Brackets and unary operators the parser will go into, as each one is a level of recursion.
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    // Where the text ends, for errors about running out of it.
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.position).map(|(token, _)| token);
+    }
+    fn column(&self) -> usize {
+        return self.tokens.get(self.position).map_or(self.end, |(_, column)| *column);
+    }
+    fn eat(&mut self, symbol: &'static str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.position += 1;
+            return true;
+        }
+        return false;
+    }
+    fn expect(&mut self, symbol: &'static str) -> Result<(), ExpressionError> {
+        if self.eat(symbol) { return Ok(()); }
+        return Err(error(format!("expected '{}'", symbol), Some(self.column())));
+    }
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Node, ExpressionError>)
+        -> Result<Node, ExpressionError> {
+        if self.depth == MAX_DEPTH {
+            return Err(error(format!("nested more than {} deep", MAX_DEPTH), Some(self.column())));
+        }
+        self.depth += 1;
+        let node: Result<Node, ExpressionError> = parse(self);
+        self.depth -= 1;
+        return node;
+    }
+    fn operator(&self) -> Option<Operator> {
+        let Some(Token::Symbol(symbol)) = self.peek() else { return None; };
+        return match *symbol {
+            "||" => Some(Operator::Or),
+            "&&" => Some(Operator::And),
+            "|" => Some(Operator::BitOr),
+            "^" => Some(Operator::BitXor),
+            "&" => Some(Operator::BitAnd),
+            "==" => Some(Operator::Equal),
+            "!=" => Some(Operator::NotEqual),
+            "<" => Some(Operator::Less),
+            "<=" => Some(Operator::LessEqual),
+            ">" => Some(Operator::Greater),
+            ">=" => Some(Operator::GreaterEqual),
+            "<<" => Some(Operator::ShiftLeft),
+            ">>" => Some(Operator::ShiftRight),
+            "+" => Some(Operator::Add),
+            "-" => Some(Operator::Subtract),
+            "*" => Some(Operator::Multiply),
+            "/" => Some(Operator::Divide),
+            "%" => Some(Operator::Remainder),
+            _ => None,
+        };
+    }
+    // Operators binding at least as tightly as `precedence`, all left associative.
+    fn binary(&mut self, precedence: u8) -> Result<Node, ExpressionError> {
+        let mut left: Node = self.unary()?;
+        while let Some(operator) = self.operator().filter(|op| op.precedence() >= precedence) {
+            self.position += 1;
+            let right: Node = self.binary(operator.precedence() + 1)?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+        return Ok(left);
+    }
+    fn unary(&mut self) -> Result<Node, ExpressionError> {
+        if self.eat("-") { return Ok(Node::Negate(Box::new(self.nested(Parser::unary)?))); }
+        if self.eat("!") { return Ok(Node::Not(Box::new(self.nested(Parser::unary)?))); }
+        if self.eat("~") { return Ok(Node::Complement(Box::new(self.nested(Parser::unary)?))); }
+        return self.primary();
+    }
+    fn primary(&mut self) -> Result<Node, ExpressionError> {
+        let column: usize = self.column();
+        let Some((token, _)) = self.tokens.get(self.position).cloned() else {
+            return Err(error("expected a value".to_string(), Some(column)));
+        };
+        self.position += 1;
+        return match token {
+            Token::Number(number) => Ok(Node::Number(number)),
+            Token::Symbol("(") => {
+                let inner: Node = self.nested(|parser| parser.binary(0))?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Token::Symbol("[") => {
+                let address: Node = self.nested(|parser| parser.binary(0))?;
+                self.expect("]")?;
+                Ok(Node::Byte(Box::new(address)))
+            }
+            Token::Name(name) if name == "w" => {
+                self.expect("[")?;
+                let address: Node = self.nested(|parser| parser.binary(0))?;
+                self.expect("]")?;
+                Ok(Node::Word(Box::new(address)))
+            }
+            Token::Name(name) => match variable(&name) {
+                Some(variable) => Ok(Node::Variable(variable)),
+                None => Err(error(format!("unknown name '{}'", name), Some(column))),
+            },
+            Token::Symbol(symbol) => Err(error(format!("unexpected '{}'", symbol), Some(column))),
+        };
+    }
+}
+
+fn variable(name: &str) -> Option<Variable> {
+    return match name {
+        "i" => Some(Variable::Index),
+        "pc" => Some(Variable::ProgramCounter),
+        "sp" => Some(Variable::StackPointer),
+        "dt" => Some(Variable::DelayTimer),
+        "st" => Some(Variable::SoundTimer),
+        "hits" => Some(Variable::Hits),
+        _ => name.strip_prefix('v')
+            .filter(|digit| digit.len() == 1)
+            .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+            .map(Variable::Register),
+    };
+}
+
+// What an expression can look at.
+pub struct Context<'a> {
+    pub state: &'a MachineState<'a>,
+    pub hits: u64,
+}
+
+impl Context<'_> {
+    fn read(&self, address: i64, length: usize) -> Result<i64, ExpressionError> {
+        let bytes: Option<&[u8]> = usize::try_from(address).ok()
+            .and_then(|address| self.state.memory.get(address..address + length));
+        let Some(bytes) = bytes else {
+            return Err(error(format!("0x{:X} is outside memory", address), None));
+        };
+        return Ok(bytes.iter().fold(0, |value, byte| value << 8 | *byte as i64));
+    }
+}
+
+fn evaluate(node: &Node, context: &Context) -> Result<i64, ExpressionError> {
+    let state: &MachineState = context.state;
+    return Ok(match node {
+        Node::Number(number) => *number,
+        Node::Variable(variable) => match *variable {
+            Variable::Register(register) => state.registers[register] as i64,
+            Variable::Index => state.index_register as i64,
+            Variable::ProgramCounter => state.program_counter as i64,
+            Variable::StackPointer => state.stack_pointer as i64,
+            Variable::DelayTimer => state.delay_timer as i64,
+            Variable::SoundTimer => state.sound_timer as i64,
+            Variable::Hits => context.hits as i64,
+        },
+        Node::Byte(address) => context.read(evaluate(address, context)?, 1)?,
+        Node::Word(address) => context.read(evaluate(address, context)?, 2)?,
+        Node::Negate(value) => evaluate(value, context)?.wrapping_neg(),
+        Node::Not(value) => (evaluate(value, context)? == 0) as i64,
+        Node::Complement(value) => !evaluate(value, context)?,
+        // Only evaluate the right hand side when it matters, so `i < 0xFFF && [i]` is safe.
+        Node::Binary(Operator::And, left, right) =>
+            (evaluate(left, context)? != 0 && evaluate(right, context)? != 0) as i64,
+        Node::Binary(Operator::Or, left, right) =>
+            (evaluate(left, context)? != 0 || evaluate(right, context)? != 0) as i64,
+        Node::Binary(operator, left, right) => {
+            let (a, b): (i64, i64) = (evaluate(left, context)?, evaluate(right, context)?);
+            match operator {
+                Operator::BitOr => a | b,
+                Operator::BitXor => a ^ b,
+                Operator::BitAnd => a & b,
+                Operator::Equal => (a == b) as i64,
+                Operator::NotEqual => (a != b) as i64,
+                Operator::Less => (a < b) as i64,
+                Operator::LessEqual => (a <= b) as i64,
+                Operator::Greater => (a > b) as i64,
+                Operator::GreaterEqual => (a >= b) as i64,
+                Operator::ShiftLeft => a.wrapping_shl(b as u32),
+                Operator::ShiftRight => a.wrapping_shr(b as u32),
+                Operator::Add => a.wrapping_add(b),
+                Operator::Subtract => a.wrapping_sub(b),
+                Operator::Multiply => a.wrapping_mul(b),
+                Operator::Divide | Operator::Remainder if b == 0 =>
+                    return Err(error("division by zero".to_string(), None)),
+                Operator::Divide => a.wrapping_div(b),
+                Operator::Remainder => a.wrapping_rem(b),
+                Operator::And | Operator::Or => unreachable!("short-circuited above."),
+            }
+        }
+    });
+}
+
+// A parsed expression, keeping its text to show it back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Expression, ExpressionError> {
+        let mut parser: Parser =
+            Parser { tokens: tokenize(text)?, position: 0, end: text.len(), depth: 0 };
+        let root: Node = parser.binary(0)?;
+        if parser.position < parser.tokens.len() {
+            return Err(error("expected an operator".to_string(), Some(parser.column())));
+        }
+        return Ok(Expression { source: text.trim().to_string(), root });
+    }
+    pub fn evaluate(&self, context: &Context) -> Result<i64, ExpressionError> {
+        return evaluate(&self.root, context);
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    // The value of an expression, in hex when true.
+    Value(Expression, bool),
+}
+
+// A message with `{expression}` in it, or `{expression:x}` for hex, filled in each time it is
+// rendered. `{{` and `}}` stand for braces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template, ExpressionError> {
+        let mut parts: Vec<Part> = Vec::new();
+        let mut literal: String = String::new();
+        let mut rest: &str = text;
+        while let Some(brace) = rest.find(['{', '}']) {
+            let column: usize = text.len() - rest.len() + brace;
+            literal.push_str(&rest[..brace]);
+            let after: &str = &rest[brace + 1..];
+            if rest[brace..].starts_with("{{") || rest[brace..].starts_with("}}") {
+                literal.push_str(&rest[brace..brace + 1]);
+                rest = &after[1..];
+                continue;
+            }
+            if rest[brace..].starts_with('}') {
+                return Err(error("unmatched '}'".to_string(), Some(column)));
+            }
+            let Some(close) = after.find('}') else {
+                return Err(error("unmatched '{'".to_string(), Some(column)));
+            };
+            let (inner, hex): (&str, bool) = match after[..close].strip_suffix(":x") {
+                Some(inner) => (inner, true),
+                None => (&after[..close], false),
+            };
+            let expression: Expression = Expression::parse(inner).map_err(|mut error| {
+                error.column = error.column.map(|inner| column + 1 + inner);
+                error
+            })?;
+            if !literal.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut literal)));
+            }
+            parts.push(Part::Value(expression, hex));
+            rest = &after[close + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        return Ok(Template { source: text.to_string(), parts });
+    }
+    // Values that cannot be worked out are shown as the error, in angle brackets.
+    pub fn render(&self, context: &Context) -> String {
+        let mut message: String = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => message.push_str(text),
+                Part::Value(expression, hex) => match expression.evaluate(context) {
+                    Ok(value) if *hex => message.push_str(&format!("0x{:X}", value)),
+                    Ok(value) => message.push_str(&value.to_string()),
+                    Err(error) => message.push_str(&format!("<{}>", error)),
+                },
+            }
+        }
+        return message;
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_state<T>(f: impl FnOnce(&Context) -> T) -> T {
+        let mut memory: Vec<u8> = vec![0; 0x1000];
+        memory[0x300..0x303].copy_from_slice(&[0x12, 0x34, 0x56]);
+        memory[0xFFF] = 0xAB;
+        let mut registers: [u8; 16] = [0; 16];
+        registers[0] = 3;
+        registers[0xF] = 1;
+        let state: MachineState = MachineState {
+            registers,
+            index_register: 0x300,
+            program_counter: 0x202,
+            stack_pointer: 0,
+            stack: &[],
+            delay_timer: 0,
+            sound_timer: 0,
+            waiting_for_key: None,
+            opcode: 0,
+            memory: &memory,
+        };
+        return f(&Context { state: &state, hits: 2 });
+    }
+
+    fn value(text: &str) -> Result<i64, ExpressionError> {
+        return with_state(|context| Expression::parse(text)?.evaluate(context));
+    }
+
+    fn render(text: &str) -> String {
+        return with_state(|context| Template::parse(text).unwrap().render(context));
+    }
+
+    #[test]
+    fn operators_bind_as_in_c() {
+        assert_eq!(value("1 + 2 * 3"), Ok(7));
+        assert_eq!(value("(1 + 2) * 3"), Ok(9));
+        assert_eq!(value("10 - 4 - 3"), Ok(3));
+        assert_eq!(value("1 << 2 + 1"), Ok(8));
+        // 1 | (6 ^ (3 & 5)), left to right would give 4.
+        assert_eq!(value("1 | 6 ^ 3 & 5"), Ok(7));
+        assert_eq!(value("1 + 1 == 2 && 3 > 2 || 0"), Ok(1));
+        assert_eq!(value("-v0 * 2"), Ok(-6));
+        assert_eq!(value("!v0 + ~0"), Ok(-1));
+        assert_eq!(value("0x10 % 6 / 2"), Ok(2));
+    }
+
+    #[test]
+    fn reads_registers_and_memory() {
+        assert_eq!(value("v0 + VF + hits"), Ok(6));
+        assert_eq!(value("pc == 0x202 && sp == 0 && dt == st"), Ok(1));
+        assert_eq!(value("[i]"), Ok(0x12));
+        assert_eq!(value("[i + 2]"), Ok(0x56));
+        assert_eq!(value("w[i]"), Ok(0x1234));
+        assert_eq!(value("W[i + 1]"), Ok(0x3456));
+        assert_eq!(value("[0xFFF]"), Ok(0xAB));
+    }
+
+    #[test]
+    fn logic_short_circuits() {
+        // The right hand side would read past memory, or divide by zero.
+        assert_eq!(value("0 && [0x1000]"), Ok(0));
+        assert_eq!(value("1 || 1 / 0"), Ok(1));
+        assert!(value("1 && [0x1000]").is_err());
+        assert!(value("0 || 1 / 0").is_err());
+        assert!(value("w[0xFFF]").is_err());
+        assert!(value("[-1]").is_err());
+    }
+
+    #[test]
+    fn nesting_is_capped() {
+        let parens = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(value(&parens(MAX_DEPTH)), Ok(1));
+        assert_eq!(value(&format!("{}1", "-".repeat(MAX_DEPTH))), Ok(1));
+        assert_eq!(Expression::parse(&parens(MAX_DEPTH + 1)),
+            Err(error("nested more than 64 deep".to_string(), Some(MAX_DEPTH + 1))));
+        assert!(Expression::parse(&format!("[{}0", "!~".repeat(MAX_DEPTH))).is_err());
+        // Far too deep to parse by recursion alone.
+        assert!(Expression::parse(&"(".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_column() {
+        let column = |text: &str| Expression::parse(text).unwrap_err().column;
+        assert_eq!(column("1 +"), Some(3));
+        assert_eq!(column("(1 + 2"), Some(6));
+        assert_eq!(column("1 2"), Some(2));
+        assert_eq!(column("v0 $ 1"), Some(3));
+        assert_eq!(column("vg"), Some(0));
+        assert_eq!(column("w 1"), Some(2));
+        assert_eq!(column("0x"), Some(0));
+    }
+
+    #[test]
+    fn templates_fill_in_values() {
+        assert_eq!(render("v0={v0} i={i:x}"), "v0=3 i=0x300");
+        assert_eq!(render("{{v0}} is {v0}}}"), "{v0} is 3}");
+        assert_eq!(render("{w[i]:x}{hits}"), "0x12342");
+        assert_eq!(render("no values"), "no values");
+        assert_eq!(render("{1 / 0}"), "<division by zero>");
+    }
+
+    #[test]
+    fn template_errors_point_into_the_text() {
+        let error = |text: &str| Template::parse(text).unwrap_err();
+        assert_eq!(error("a } b").message, "unmatched '}'");
+        assert_eq!(error("a {v0").column, Some(2));
+        // Column of the bad name inside the braces, counted from the start of the template.
+        assert_eq!(error("ab {v0 + vg}").column, Some(9));
+        assert_eq!(Template::parse("x {v0:x} y").unwrap().to_string(), "x {v0:x} y");
+    }
+}
@@ -1,33 +1,145 @@
+use super::api::{Frame, Region};
+use super::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
 // Graphics
-const PIXELS: usize = 64 * 32;
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+const PIXELS: usize = WIDTH * HEIGHT;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum PixelState { ON, OFF }
+const SPRITE_WIDTH: usize = 8;
 
 pub struct Graphics {
-    display: [PixelState; PIXELS]
+    display: [bool; PIXELS],
+    // Sprites running off the edge are cut off when set, otherwise they wrap around.
+    clipping: bool,
+    // Bounding box (min x, min y, max x, max y) of the pixels changed since the last frame.
+    dirty: Option<(usize, usize, usize, usize)>,
 }
 
 impl Graphics {
     pub fn new() -> Graphics {
         Graphics {
-            display: [PixelState::ON; PIXELS]
+            display: [false; PIXELS],
+            clipping: true,
+            dirty: None,
         }
     }
+    pub fn set_clipping(&mut self, clipping: bool) {
+        self.clipping = clipping;
+    }
     pub fn clear(&mut self) {
-        self.display = [PixelState::OFF; PIXELS];
+        self.display = [false; PIXELS];
+        self.dirty = Some((0, 0, WIDTH - 1, HEIGHT - 1));
     }
-    pub fn draw(&mut self, bytes: Vec<u8>, x_coordinate: usize, y_coordinate: usize) -> bool {
-        let pixels_erased: bool = false;
-        for (i, byte) in bytes.iter().enumerate() {
-            self.display[x_coordinate + (y_coordinate * 64)] = PixelState::ON; //  XOR
-        }
-        println!("{:?}, {}, {}", bytes, x_coordinate, y_coordinate);
-        for (i, pixel) in self.display.iter().enumerate() {
-            print!("{}", if *pixel == PixelState::ON {"◒"} else {" "});
-            if i % 64 == 0 { println!(); }
+    // XOR the sprite onto the display, returning whether any pixel was turned off.
+    pub fn draw(&mut self, bytes: &[u8], x_coordinate: usize, y_coordinate: usize) -> bool {
+        let mut pixels_erased: bool = false;
+        // The starting position always wraps, only the body of the sprite is clipped.
+        let x_start: usize = x_coordinate % WIDTH;
+        let y_start: usize = y_coordinate % HEIGHT;
+        for (row, byte) in bytes.iter().enumerate() {
+            let y: usize = y_start + row;
+            if y >= HEIGHT && self.clipping { break; }
+            for column in 0..SPRITE_WIDTH {
+                if byte & (0x80 >> column) == 0 { continue; }
+                let x: usize = x_start + column;
+                if x >= WIDTH && self.clipping { break; }
+
+                let (x, y) = (x % WIDTH, y % HEIGHT);
+                let pixel: &mut bool = &mut self.display[x + y * WIDTH];
+                pixels_erased |= *pixel;
+                *pixel = !*pixel;
+                self.mark_dirty(x, y);
+            }
         }
-        println!("\n\n");
         return pixels_erased;
     }
+    pub fn is_dirty(&self) -> bool {
+        return self.dirty.is_some();
+    }
+    // Hand out the changed region and start tracking a fresh one.
+    pub fn take_dirty(&mut self) -> Option<Region> {
+        return self.dirty.take().map(|(min_x, min_y, max_x, max_y)| Region {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x + 1,
+            height: max_y - min_y + 1,
+        });
+    }
+    pub fn frame(&self, dirty: Region) -> Frame<'_> {
+        Frame {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: &self.display,
+            dirty,
+        }
+    }
+    fn mark_dirty(&mut self, x: usize, y: usize) {
+        self.dirty = Some(match self.dirty {
+            Some((min_x, min_y, max_x, max_y)) =>
+                (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+            None => (x, y, x, y),
+        });
+    }
+}
+
+impl Default for Graphics {
+    fn default() -> Graphics {
+        return Graphics::new();
+    }
+}
+
+impl SaveState for Graphics {
+    // Eight pixels to a byte.
+    fn save(&self, writer: &mut StateWriter) -> () {
+        for pixels in self.display.chunks(8) {
+            let byte: u8 = pixels.iter().fold(0, |byte, pixel| (byte << 1) | *pixel as u8);
+            writer.put_u8(byte);
+        }
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let bytes: &[u8] = reader.take(PIXELS / 8)?;
+        for (i, pixel) in self.display.iter_mut().enumerate() {
+            *pixel = bytes[i / 8] & (0x80 >> (i % 8)) != 0;
+        }
+        self.dirty = Some((0, 0, WIDTH - 1, HEIGHT - 1));
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_dirty_covers_every_changed_pixel_once() {
+        let mut graphics: Graphics = Graphics::new();
+        assert!(!graphics.is_dirty());
+        assert_eq!(graphics.take_dirty(), None);
+
+        graphics.draw(&[0x80], 10, 4);
+        graphics.draw(&[0xC0, 0x40], 20, 6);
+        assert!(graphics.is_dirty());
+        assert_eq!(graphics.take_dirty(), Some(Region { x: 10, y: 4, width: 12, height: 4 }));
+        assert!(!graphics.is_dirty());
+        assert_eq!(graphics.take_dirty(), None);
+
+        // Erasing is a change too.
+        assert!(graphics.draw(&[0x80], 10, 4));
+        assert_eq!(graphics.take_dirty(), Some(Region { x: 10, y: 4, width: 1, height: 1 }));
+    }
+
+    #[test]
+    fn clear_and_wrapped_sprites_mark_the_screen() {
+        let mut graphics: Graphics = Graphics::new();
+        graphics.clear();
+        assert_eq!(graphics.take_dirty(),
+            Some(Region { x: 0, y: 0, width: WIDTH, height: HEIGHT }));
+
+        // Without clipping, the right half of the sprite lands in column 0.
+        graphics.set_clipping(false);
+        graphics.draw(&[0x03], WIDTH - 7, 0);
+        assert_eq!(graphics.take_dirty(), Some(Region { x: 0, y: 0, width: WIDTH, height: 1 }));
+        assert!(graphics.frame(Region { x: 0, y: 0, width: 0, height: 0 }).pixel(0, 0));
+    }
 }
@@ -0,0 +1,302 @@
+use std::fmt;
+
+use crate::common::hex_utils;
+
+// Every CHIP-8 instruction, named after Cowgod's reference. X and Y are register indices, KK a
+// byte, N a nibble and the addresses are the low 12 bits of the opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Sys(u16),                             // 0NNN
+    Cls,                                  // 00E0
+    Ret,                                  // 00EE
+    Jp(u16),                              // 1NNN
+    Call(u16),                            // 2NNN
+    SeVxByte { x: usize, kk: u8 },        // 3XKK
+    SneVxByte { x: usize, kk: u8 },       // 4XKK
+    SeVxVy { x: usize, y: usize },        // 5XY0
+    LdVxByte { x: usize, kk: u8 },        // 6XKK
+    AddVxByte { x: usize, kk: u8 },       // 7XKK
+    LdVxVy { x: usize, y: usize },        // 8XY0
+    Or { x: usize, y: usize },            // 8XY1
+    And { x: usize, y: usize },           // 8XY2
+    Xor { x: usize, y: usize },           // 8XY3
+    AddVxVy { x: usize, y: usize },       // 8XY4
+    Sub { x: usize, y: usize },           // 8XY5
+    Shr { x: usize, y: usize },           // 8XY6
+    Subn { x: usize, y: usize },          // 8XY7
+    Shl { x: usize, y: usize },           // 8XYE
+    SneVxVy { x: usize, y: usize },       // 9XY0
+    LdI(u16),                             // ANNN
+    JpV0(u16),                            // BNNN
+    Rnd { x: usize, kk: u8 },             // CXKK
+    Drw { x: usize, y: usize, n: u8 },    // DXYN
+    Skp { x: usize },                     // EX9E
+    Sknp { x: usize },                    // EXA1
+    LdVxDt { x: usize },                  // FX07
+    LdVxK { x: usize },                   // FX0A
+    LdDtVx { x: usize },                  // FX15
+    LdStVx { x: usize },                  // FX18
+    AddIVx { x: usize },                  // FX1E
+    LdFVx { x: usize },                   // FX29
+    LdBVx { x: usize },                   // FX33
+    LdIVx { x: usize },                   // FX55
+    LdVxI { x: usize },                   // FX65
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown opcode {:04X}", self.opcode)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    let address: u16 = hex_utils::left_pad(opcode, 1);
+    let x: usize = hex_utils::right_shift(opcode & 0x0F00, 2) as usize;
+    let y: usize = hex_utils::right_shift(opcode & 0x00F0, 1) as usize;
+    let n: u8 = (opcode & 0x000F) as u8;
+    let kk: u8 = (opcode & 0x00FF) as u8;
+
+    let instruction: Instruction = match (hex_utils::right_shift(opcode, 3), n) {
+        (0x0, _) => match address {
+            0x0E0 => Instruction::Cls,
+            0x0EE => Instruction::Ret,
+            _ => Instruction::Sys(address),
+        },
+        (0x1, _) => Instruction::Jp(address),
+        (0x2, _) => Instruction::Call(address),
+        (0x3, _) => Instruction::SeVxByte { x, kk },
+        (0x4, _) => Instruction::SneVxByte { x, kk },
+        (0x5, 0x0) => Instruction::SeVxVy { x, y },
+        (0x6, _) => Instruction::LdVxByte { x, kk },
+        (0x7, _) => Instruction::AddVxByte { x, kk },
+        (0x8, 0x0) => Instruction::LdVxVy { x, y },
+        (0x8, 0x1) => Instruction::Or { x, y },
+        (0x8, 0x2) => Instruction::And { x, y },
+        (0x8, 0x3) => Instruction::Xor { x, y },
+        (0x8, 0x4) => Instruction::AddVxVy { x, y },
+        (0x8, 0x5) => Instruction::Sub { x, y },
+        (0x8, 0x6) => Instruction::Shr { x, y },
+        (0x8, 0x7) => Instruction::Subn { x, y },
+        (0x8, 0xE) => Instruction::Shl { x, y },
+        (0x9, 0x0) => Instruction::SneVxVy { x, y },
+        (0xA, _) => Instruction::LdI(address),
+        (0xB, _) => Instruction::JpV0(address),
+        (0xC, _) => Instruction::Rnd { x, kk },
+        (0xD, _) => Instruction::Drw { x, y, n },
+        (0xE, _) => match kk {
+            0x9E => Instruction::Skp { x },
+            0xA1 => Instruction::Sknp { x },
+            _ => return Err(DecodeError { opcode }),
+        },
+        (0xF, _) => match kk {
+            0x07 => Instruction::LdVxDt { x },
+            0x0A => Instruction::LdVxK { x },
+            0x15 => Instruction::LdDtVx { x },
+            0x18 => Instruction::LdStVx { x },
+            0x1E => Instruction::AddIVx { x },
+            0x29 => Instruction::LdFVx { x },
+            0x33 => Instruction::LdBVx { x },
+            0x55 => Instruction::LdIVx { x },
+            0x65 => Instruction::LdVxI { x },
+            _ => return Err(DecodeError { opcode }),
+        },
+        _ => return Err(DecodeError { opcode }),
+    };
+    return Ok(instruction);
+}
+
+// The inverse of `decode`, register indices and immediates are masked to their field widths.
+pub fn encode(instruction: &Instruction) -> u16 {
+    let xkk = |high: u16, x: usize, kk: u8| high | ((x as u16 & 0xF) << 8) | kk as u16;
+    let xyn = |high: u16, x: usize, y: usize, n: u16|
+        high | ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | (n & 0xF);
+    let fx = |x: usize, kk: u16| 0xF000 | ((x as u16 & 0xF) << 8) | kk;
+    return match *instruction {
+        Instruction::Sys(address) => address & 0x0FFF,
+        Instruction::Cls => 0x00E0,
+        Instruction::Ret => 0x00EE,
+        Instruction::Jp(address) => 0x1000 | (address & 0x0FFF),
+        Instruction::Call(address) => 0x2000 | (address & 0x0FFF),
+        Instruction::SeVxByte { x, kk } => xkk(0x3000, x, kk),
+        Instruction::SneVxByte { x, kk } => xkk(0x4000, x, kk),
+        Instruction::SeVxVy { x, y } => xyn(0x5000, x, y, 0x0),
+        Instruction::LdVxByte { x, kk } => xkk(0x6000, x, kk),
+        Instruction::AddVxByte { x, kk } => xkk(0x7000, x, kk),
+        Instruction::LdVxVy { x, y } => xyn(0x8000, x, y, 0x0),
+        Instruction::Or { x, y } => xyn(0x8000, x, y, 0x1),
+        Instruction::And { x, y } => xyn(0x8000, x, y, 0x2),
+        Instruction::Xor { x, y } => xyn(0x8000, x, y, 0x3),
+        Instruction::AddVxVy { x, y } => xyn(0x8000, x, y, 0x4),
+        Instruction::Sub { x, y } => xyn(0x8000, x, y, 0x5),
+        Instruction::Shr { x, y } => xyn(0x8000, x, y, 0x6),
+        Instruction::Subn { x, y } => xyn(0x8000, x, y, 0x7),
+        Instruction::Shl { x, y } => xyn(0x8000, x, y, 0xE),
+        Instruction::SneVxVy { x, y } => xyn(0x9000, x, y, 0x0),
+        Instruction::LdI(address) => 0xA000 | (address & 0x0FFF),
+        Instruction::JpV0(address) => 0xB000 | (address & 0x0FFF),
+        Instruction::Rnd { x, kk } => xkk(0xC000, x, kk),
+        Instruction::Drw { x, y, n } => xyn(0xD000, x, y, n as u16),
+        Instruction::Skp { x } => xkk(0xE000, x, 0x9E),
+        Instruction::Sknp { x } => xkk(0xE000, x, 0xA1),
+        Instruction::LdVxDt { x } => fx(x, 0x07),
+        Instruction::LdVxK { x } => fx(x, 0x0A),
+        Instruction::LdDtVx { x } => fx(x, 0x15),
+        Instruction::LdStVx { x } => fx(x, 0x18),
+        Instruction::AddIVx { x } => fx(x, 0x1E),
+        Instruction::LdFVx { x } => fx(x, 0x29),
+        Instruction::LdBVx { x } => fx(x, 0x33),
+        Instruction::LdIVx { x } => fx(x, 0x55),
+        Instruction::LdVxI { x } => fx(x, 0x65),
+    };
+}
+
+impl Instruction {
+    // The address operand of jumps, calls and loads into I.
+    pub fn target(&self) -> Option<u16> {
+        return match *self {
+            Instruction::Sys(address)
+            | Instruction::Jp(address)
+            | Instruction::Call(address)
+            | Instruction::LdI(address)
+            | Instruction::JpV0(address) => Some(address),
+            _ => None,
+        };
+    }
+    // The opcode with its operands as placeholders, e.g. "8XY4".
+    pub fn pattern(&self) -> &'static str {
+        return match *self {
+            Instruction::Sys(_) => "0NNN",
+            Instruction::Cls => "00E0",
+            Instruction::Ret => "00EE",
+            Instruction::Jp(_) => "1NNN",
+            Instruction::Call(_) => "2NNN",
+            Instruction::SeVxByte { .. } => "3XKK",
+            Instruction::SneVxByte { .. } => "4XKK",
+            Instruction::SeVxVy { .. } => "5XY0",
+            Instruction::LdVxByte { .. } => "6XKK",
+            Instruction::AddVxByte { .. } => "7XKK",
+            Instruction::LdVxVy { .. } => "8XY0",
+            Instruction::Or { .. } => "8XY1",
+            Instruction::And { .. } => "8XY2",
+            Instruction::Xor { .. } => "8XY3",
+            Instruction::AddVxVy { .. } => "8XY4",
+            Instruction::Sub { .. } => "8XY5",
+            Instruction::Shr { .. } => "8XY6",
+            Instruction::Subn { .. } => "8XY7",
+            Instruction::Shl { .. } => "8XYE",
+            Instruction::SneVxVy { .. } => "9XY0",
+            Instruction::LdI(_) => "ANNN",
+            Instruction::JpV0(_) => "BNNN",
+            Instruction::Rnd { .. } => "CXKK",
+            Instruction::Drw { .. } => "DXYN",
+            Instruction::Skp { .. } => "EX9E",
+            Instruction::Sknp { .. } => "EXA1",
+            Instruction::LdVxDt { .. } => "FX07",
+            Instruction::LdVxK { .. } => "FX0A",
+            Instruction::LdDtVx { .. } => "FX15",
+            Instruction::LdStVx { .. } => "FX18",
+            Instruction::AddIVx { .. } => "FX1E",
+            Instruction::LdFVx { .. } => "FX29",
+            Instruction::LdBVx { .. } => "FX33",
+            Instruction::LdIVx { .. } => "FX55",
+            Instruction::LdVxI { .. } => "FX65",
+        };
+    }
+    // Cowgod-style assembly, with `name` deciding how an address operand is written.
+    pub fn mnemonic(&self, name: &dyn Fn(u16) -> String) -> String {
+        return match *self {
+            Instruction::Sys(address) => format!("SYS {}", name(address)),
+            Instruction::Cls => "CLS".to_string(),
+            Instruction::Ret => "RET".to_string(),
+            Instruction::Jp(address) => format!("JP {}", name(address)),
+            Instruction::Call(address) => format!("CALL {}", name(address)),
+            Instruction::SeVxByte { x, kk } => format!("SE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SneVxByte { x, kk } => format!("SNE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SeVxVy { x, y } => format!("SE V{:X}, V{:X}", x, y),
+            Instruction::LdVxByte { x, kk } => format!("LD V{:X}, 0x{:02X}", x, kk),
+            Instruction::AddVxByte { x, kk } => format!("ADD V{:X}, 0x{:02X}", x, kk),
+            Instruction::LdVxVy { x, y } => format!("LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+            Instruction::AddVxVy { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+            Instruction::SneVxVy { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(address) => format!("LD I, {}", name(address)),
+            Instruction::JpV0(address) => format!("JP V0, {}", name(address)),
+            Instruction::Rnd { x, kk } => format!("RND V{:X}, 0x{:02X}", x, kk),
+            Instruction::Drw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp { x } => format!("SKP V{:X}", x),
+            Instruction::Sknp { x } => format!("SKNP V{:X}", x),
+            Instruction::LdVxDt { x } => format!("LD V{:X}, DT", x),
+            Instruction::LdVxK { x } => format!("LD V{:X}, K", x),
+            Instruction::LdDtVx { x } => format!("LD DT, V{:X}", x),
+            Instruction::LdStVx { x } => format!("LD ST, V{:X}", x),
+            Instruction::AddIVx { x } => format!("ADD I, V{:X}", x),
+            Instruction::LdFVx { x } => format!("LD F, V{:X}", x),
+            Instruction::LdBVx { x } => format!("LD B, V{:X}", x),
+            Instruction::LdIVx { x } => format!("LD [I], V{:X}", x),
+            Instruction::LdVxI { x } => format!("LD V{:X}, [I]", x),
+        };
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic(&|address| format!("0x{:03X}", address)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_undoes_decode_for_every_opcode() {
+        let mut decoded: usize = 0;
+        for opcode in 0..=0xFFFF {
+            if let Ok(instruction) = decode(opcode) {
+                assert_eq!(encode(&instruction), opcode, "{:04X} {}", opcode, instruction);
+                decoded += 1;
+            }
+        }
+        // 0NNN, 1NNN, 2NNN, 3XKK, 4XKK, 6XKK, 7XKK, ANNN, BNNN, CXKK and DXYN use all 4096
+        // opcodes, 5XY0, 8XY_ (nine of them) and 9XY0 256 each, EX__ (two) and FX__ (nine) 16.
+        assert_eq!(decoded, 11 * 4096 + 11 * 256 + 11 * 16);
+    }
+
+    #[test]
+    fn decodes_operands_from_their_nibbles() {
+        assert_eq!(decode(0xD3A5), Ok(Instruction::Drw { x: 3, y: 0xA, n: 5 }));
+        assert_eq!(decode(0x8CDE), Ok(Instruction::Shl { x: 0xC, y: 0xD }));
+        assert_eq!(decode(0x7B80), Ok(Instruction::AddVxByte { x: 0xB, kk: 0x80 }));
+        assert_eq!(decode(0xB2F0), Ok(Instruction::JpV0(0x2F0)));
+        assert_eq!(decode(0xF765), Ok(Instruction::LdVxI { x: 7 }));
+        assert_eq!(decode(0x00E0), Ok(Instruction::Cls));
+        assert_eq!(decode(0x0123), Ok(Instruction::Sys(0x123)));
+    }
+
+    #[test]
+    fn rejects_unknown_opcodes() {
+        for opcode in [0x5121, 0x8128, 0x912F, 0xE19F, 0xF1FF] {
+            assert_eq!(decode(opcode), Err(DecodeError { opcode }));
+        }
+    }
+
+    #[test]
+    fn encode_masks_operands_to_their_fields() {
+        assert_eq!(encode(&Instruction::Jp(0x1234)), 0x1234);
+        assert_eq!(encode(&Instruction::LdVxByte { x: 0x13, kk: 0x42 }), 0x6342);
+        assert_eq!(encode(&Instruction::Drw { x: 1, y: 2, n: 0x1F }), 0xD12F);
+    }
+}
@@ -1,7 +1,11 @@
-#[derive(Clone, Copy, PartialEq, Eq)]
+use std::time::Instant;
+
+use super::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Position { UP, DOWN }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Key {
     ZERO = 0,
     ONE = 1,
@@ -21,20 +25,188 @@ pub enum Key {
     F = 15,
 }
 
+impl Key {
+    pub const ALL: [Key; 16] = [
+        Key::ZERO, Key::ONE, Key::TWO, Key::THREE, Key::FOUR, Key::FIVE, Key::SIX, Key::SEVEN,
+        Key::EIGHT, Key::NINE, Key::A, Key::B, Key::C, Key::D, Key::E, Key::F,
+    ];
+
+    pub fn from_index(index: u8) -> Option<Key> {
+        return Key::ALL.get(index as usize).copied();
+    }
+    // What the program sees, e.g. in VX after FX0A.
+    pub fn index(self) -> u8 {
+        return self as u8;
+    }
+}
+
+// A change reported by the frontend, held in a queue until the next frame boundary.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub position: Position,
+    // Orders events that reach the queue out of order.
+    pub at: Instant,
+}
+
+// When FX0A lets the program continue.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyWaitMode {
+    // As soon as a key goes down.
+    Press,
+    // Once a key has gone down and come back up again, as on the COSMAC VIP.
+    Release,
+}
+
 pub struct Keypad {
     keys: [Position; 16],
+    queue: Vec<KeyEvent>,
+    // Bit per key, set when the key went down (or came up) in the last batch of events. A key
+    // tapped within a single frame shows up in both.
+    pressed: u16,
+    released: u16,
 }
 
 impl Keypad {
     pub fn new() -> Keypad {
         Keypad {
             keys: [Position::UP; 16],
+            queue: Vec::new(),
+            pressed: 0,
+            released: 0,
         }
     }
-    pub fn set_key(&mut self, key: u8, position: Position) {
+    pub fn queue(&mut self, event: KeyEvent) {
+        self.queue.push(event);
+    }
+    // Apply everything queued since the last frame boundary, handing back the events that
+    // changed a key.
+    pub fn apply_events(&mut self) -> Vec<KeyEvent> {
+        self.pressed = 0;
+        self.released = 0;
+        let mut events: Vec<KeyEvent> = std::mem::take(&mut self.queue);
+        events.sort_by_key(|event| event.at);
+        events.retain(|event| self.set_key(event.key, event.position));
+        return events;
+    }
+    // Change a key right away, skipping the queue. Returns false if it was already there.
+    pub fn set_key(&mut self, key: Key, position: Position) -> bool {
+        if self.keys[key as usize] == position {
+            return false;
+        }
         self.keys[key as usize] = position;
+        match position {
+            Position::DOWN => self.pressed |= 1 << key as u16,
+            Position::UP => self.released |= 1 << key as u16,
+        }
+        return true;
+    }
+    pub fn get_key(&self, key: Key) -> Position {
+        return self.keys[key as usize];
+    }
+    pub fn is_down(&self, key: Key) -> bool {
+        return self.get_key(key) == Position::DOWN;
+    }
+    pub fn just_pressed(&self, key: Key) -> bool {
+        return self.pressed & (1 << key as u16) != 0;
+    }
+    pub fn just_released(&self, key: Key) -> bool {
+        return self.released & (1 << key as u16) != 0;
+    }
+    // The lowest key that went down in the last batch of events, if any.
+    pub fn first_just_pressed(&self) -> Option<Key> {
+        return Key::ALL.into_iter().find(|key| self.just_pressed(*key));
+    }
+    fn held(&self) -> u16 {
+        return Key::ALL.into_iter().filter(|key| self.is_down(*key))
+            .fold(0, |held, key| held | 1 << key as u16);
     }
-    pub fn get_key(&self, key: u8) -> Position {
-        return self.keys[key as usize]
+}
+
+impl Default for Keypad {
+    fn default() -> Keypad {
+        return Keypad::new();
+    }
+}
+
+impl SaveState for Keypad {
+    // Queued events are still to come from the host, they are not part of the machine.
+    fn save(&self, writer: &mut StateWriter) -> () {
+        writer.put_u16(self.held());
+        writer.put_u16(self.pressed);
+        writer.put_u16(self.released);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let held: u16 = reader.get_u16()?;
+        for key in Key::ALL {
+            self.keys[key as usize] =
+                if held & (1 << key as u16) != 0 {Position::DOWN} else {Position::UP};
+        }
+        self.pressed = reader.get_u16()?;
+        self.released = reader.get_u16()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn event(key: Key, position: Position, at: Instant) -> KeyEvent {
+        return KeyEvent { key, position, at };
+    }
+
+    #[test]
+    fn queued_events_wait_for_the_frame_boundary() {
+        let mut keypad: Keypad = Keypad::new();
+        keypad.queue(event(Key::FIVE, Position::DOWN, Instant::now()));
+        assert!(!keypad.is_down(Key::FIVE));
+        assert!(!keypad.just_pressed(Key::FIVE));
+        assert_eq!(keypad.apply_events().len(), 1);
+        assert!(keypad.is_down(Key::FIVE));
+        assert!(keypad.just_pressed(Key::FIVE));
+    }
+
+    #[test]
+    fn a_tap_within_one_frame_is_both_pressed_and_released() {
+        let mut keypad: Keypad = Keypad::new();
+        let now: Instant = Instant::now();
+        // Out of order on purpose, they are applied by time.
+        keypad.queue(event(Key::A, Position::UP, now + Duration::from_millis(5)));
+        keypad.queue(event(Key::A, Position::DOWN, now));
+        assert_eq!(keypad.apply_events().len(), 2);
+        assert!(!keypad.is_down(Key::A));
+        assert!(keypad.just_pressed(Key::A));
+        assert!(keypad.just_released(Key::A));
+        assert_eq!(keypad.first_just_pressed(), Some(Key::A));
+    }
+
+    #[test]
+    fn edges_last_one_frame() {
+        let mut keypad: Keypad = Keypad::new();
+        keypad.queue(event(Key::ONE, Position::DOWN, Instant::now()));
+        keypad.apply_events();
+        assert!(keypad.apply_events().is_empty());
+        assert!(keypad.is_down(Key::ONE));
+        assert!(!keypad.just_pressed(Key::ONE));
+        assert_eq!(keypad.first_just_pressed(), None);
+
+        keypad.queue(event(Key::ONE, Position::UP, Instant::now()));
+        keypad.apply_events();
+        assert!(keypad.just_released(Key::ONE));
+        keypad.apply_events();
+        assert!(!keypad.just_released(Key::ONE));
+    }
+
+    #[test]
+    fn repeats_of_the_current_position_are_dropped() {
+        let mut keypad: Keypad = Keypad::new();
+        let now: Instant = Instant::now();
+        keypad.queue(event(Key::F, Position::DOWN, now));
+        keypad.queue(event(Key::F, Position::DOWN, now + Duration::from_millis(30)));
+        keypad.queue(event(Key::B, Position::UP, now));
+        let applied: Vec<KeyEvent> = keypad.apply_events();
+        assert_eq!(applied, [event(Key::F, Position::DOWN, now)]);
     }
 }
@@ -1,21 +1,22 @@
+use super::error::EmulatorError;
+use super::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
 // Memory
 const MEMORY_SIZE: usize = 0x1000;
 
-const PROGRAM_START: usize = 0x200;
-const PROGRAM_END: usize = 0xFFF;
-const MEMORY_START: usize= 0x000;
+pub const PROGRAM_START: usize = 0x200;
+pub const PROGRAM_END: usize = 0xFFF;
 const MEMORY_END: usize = 0x1FF;
 
-// I know there is another popular font start and end but I don't know why so I refuse.
-const FONT_START: u16 = 0x000;
-const FONT_END: u16 = 0x04B;
-
 const ONE_BYTE: usize = 8;
 
+// The font starts at 0x000. I know there is another popular font start and end but I don't know
+// why so I refuse.
 const SPRITE_LOCATIONS: [u8; 16] = [
     0, 5, 10, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60, 65, 70, 75];
 
-const SPRITE_DATA: [u8; 80] = [
+// The built in font, another one can be given to `Memory::with_font`.
+pub const SPRITE_DATA: [u8; 80] = [
     // Sprite data to be loaded into protected memory
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -35,44 +36,122 @@ const SPRITE_DATA: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// The first address of an access starting at `starting_index` that falls outside memory.
+fn out_of_bounds(starting_index: usize) -> EmulatorError {
+    return EmulatorError::MemoryOutOfBounds { addr: starting_index.max(MEMORY_SIZE) };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+// A read or write of `length` bytes from `address` on, by the running program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub address: u16,
+    pub length: u16,
+}
+
 pub struct Memory {
-    memory: [u8; MEMORY_SIZE]
+    memory: [u8; MEMORY_SIZE],
+    // Copied into protected memory each time a ROM is loaded.
+    font: [u8; 80],
+    // Accesses since they were last taken, while tracing for watchpoints.
+    accesses: Option<Vec<Access>>,
 }
 
 impl Memory {
     pub fn new() -> Memory {
+        return Memory::with_font(SPRITE_DATA);
+    }
+    // Sixteen 4x5 hex digit sprites, five bytes each, from 0 to F.
+    pub fn with_font(font: [u8; 80]) -> Memory {
         // TODO: Find a way to initialize rom data to memory immediately
         Memory {
-            memory: [0; MEMORY_SIZE]
+            memory: [0; MEMORY_SIZE],
+            font,
+            accesses: None,
         }
     }
-    pub fn load(&mut self, program: &Vec<u8>) {
+    pub fn load(&mut self, program: &[u8]) -> Result<(), EmulatorError> {
         assert!(SPRITE_DATA.len() < MEMORY_END); // Make sure the rom never goes past protected mem.
-        for (i, byte) in SPRITE_DATA.iter().enumerate() {
+        if program.len() > PROGRAM_END + 1 - PROGRAM_START { // Make sure program is within mem.
+            return Err(EmulatorError::RomTooLarge { size: program.len() });
+        }
+        // Nothing of a previous, longer, ROM is left behind.
+        self.memory = [0; MEMORY_SIZE];
+        for (i, byte) in self.font.iter().enumerate() {
             self.memory[i] = *byte;
         }
-        assert!(program.len() < PROGRAM_END - PROGRAM_START); // Make sure program is within mem.
         for (i, byte) in program.iter().enumerate() {
             self.memory[PROGRAM_START + i] = *byte;
         }
-        println!("loaded program");
+        return Ok(());
     }
-    pub fn get_opcode(&mut self, address: usize) -> u16 {
+    pub fn get_opcode(&mut self, address: usize) -> Result<u16, EmulatorError> {
+        if address + 1 >= MEMORY_SIZE {
+            return Err(EmulatorError::PcOutOfRange { pc: address as u16 });
+        }
+        self.log(AccessKind::Read, address, 2);
         let big_end: u16 = (self.memory[address] as u16) << ONE_BYTE; // Left shift one whole byte
         let little_end: u16 = self.memory[address + 1] as u16; // Store the next byte as u16
-        return big_end + little_end; // Add together and return
+        return Ok(big_end + little_end); // Add together and return
     }
-    pub fn get_bytes(&mut self, starting_index: u16, number_of_bytes: u8) -> Vec<u8> {
-        let ending_index: u16 = starting_index + number_of_bytes as u16;
-        return self.memory[starting_index as usize..ending_index as usize].to_vec();
+    pub fn get_bytes(&mut self, starting_index: u16, number_of_bytes: u8)
+            -> Result<Vec<u8>, EmulatorError> {
+        let ending_index: usize = starting_index as usize + number_of_bytes as usize;
+        self.log(AccessKind::Read, starting_index as usize, number_of_bytes as usize);
+        return match self.memory.get(starting_index as usize..ending_index) {
+            Some(bytes) => Ok(bytes.to_vec()),
+            None => Err(out_of_bounds(starting_index as usize)),
+        };
     }
-    pub fn set_bytes(&mut self, starting_index: usize, bytes: Vec<u8>) {
-        let start: usize = starting_index - 1;
-        for (i, byte) in bytes.iter().enumerate() {
-            self.memory[start + i] = *byte;
+    pub fn set_bytes(&mut self, starting_index: usize, bytes: Vec<u8>)
+        -> Result<(), EmulatorError> {
+        let ending_index: usize = starting_index + bytes.len();
+        self.log(AccessKind::Write, starting_index, bytes.len());
+        match self.memory.get_mut(starting_index..ending_index) {
+            Some(destination) => destination.copy_from_slice(&bytes),
+            None => return Err(out_of_bounds(starting_index)),
         }
+        return Ok(());
+    }
+    // Keep track of reads and writes until turned off again. Fetching an instruction counts as
+    // reading its two bytes, so a read watch on code stops when it runs.
+    pub fn trace_accesses(&mut self, on: bool) {
+        self.accesses = if on {Some(Vec::new())} else {None};
+    }
+    pub fn take_accesses(&mut self) -> Vec<Access> {
+        return self.accesses.as_mut().map(std::mem::take).unwrap_or_default();
+    }
+    fn log(&mut self, kind: AccessKind, address: usize, length: usize) {
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(Access { kind, address: address as u16, length: length as u16 });
+        }
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        return &self.memory;
     }
     pub fn get_hex_sprite_index(&mut self, hex_digit: u8) -> u8 {
-        return SPRITE_LOCATIONS[hex_digit as usize];
+        return SPRITE_LOCATIONS[(hex_digit & 0xF) as usize]; // Only the low nibble is a digit
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        return Memory::new();
+    }
+}
+
+impl SaveState for Memory {
+    fn save(&self, writer: &mut StateWriter) -> () {
+        writer.put_bytes(&self.memory);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.memory.copy_from_slice(reader.take(MEMORY_SIZE)?);
+        return Ok(());
     }
 }
@@ -1,7 +1,22 @@
 pub mod chip_eight;
-mod api;
+pub mod builder;
+pub mod api;
 mod processor;
-mod memory;
-mod graphics;
+pub mod memory;
+pub mod graphics;
 mod sound;
-mod keypad;
+pub mod keypad;
+pub mod scheduler;
+pub mod quirks;
+pub mod random;
+pub mod instruction;
+pub mod disassembler;
+pub mod assembler;
+pub mod error;
+pub mod crash_report;
+pub mod save_state;
+pub mod rewind;
+pub mod movie;
+pub mod rom_info;
+pub mod debugger;
+pub mod expression;
@@ -0,0 +1,271 @@
+use std::fmt;
+
+use super::chip_eight::ChipEight;
+use super::error::EmulatorError;
+use super::keypad::{Key, Position};
+use super::quirks::Quirks;
+use super::random::RandomMode;
+use super::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+use super::scheduler::Speed;
+use crate::common::hash_utils;
+
+// File layout: magic, format version, then the fields of `Movie` in order.
+const MAGIC: &[u8; 4] = b"C8MV";
+pub const VERSION: u8 = 1;
+// Frames between state checksums by default, a desync is pinned down to within this many
+// frames. Record with an interval of 1 to find the exact frame.
+pub const CHECKSUM_INTERVAL: u64 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyChange {
+    // Applied as this frame starts.
+    pub frame: u64,
+    pub key: Key,
+    pub position: Position,
+}
+
+// Everything needed to replay a session: how the machine was set up, the state it was in when
+// recording started and every key change after that.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_sha1: [u8; 20],
+    pub seed: u64,
+    pub random_mode: RandomMode,
+    pub speed: Speed,
+    pub quirks: Quirks,
+    pub start_state: Vec<u8>,
+    pub start_frame: u64,
+    pub end_frame: u64,
+    pub checksum_interval: u64,
+    pub events: Vec<KeyChange>,
+    // (frame, checksum of the machine state once that frame has run).
+    pub checksums: Vec<(u64, u32)>,
+}
+
+pub fn checksum(chip_eight: &ChipEight) -> u32 {
+    let digest: [u8; 20] = hash_utils::sha1(&chip_eight.save_state());
+    return u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+}
+
+impl Movie {
+    // Start recording from wherever the machine is now.
+    pub fn record(chip_eight: &ChipEight, checksum_interval: u64) -> Movie {
+        return Movie {
+            rom_sha1: chip_eight.rom_sha1(),
+            seed: chip_eight.seed(),
+            random_mode: chip_eight.random_mode(),
+            speed: chip_eight.speed(),
+            quirks: chip_eight.quirks(),
+            start_state: chip_eight.save_state(),
+            start_frame: chip_eight.frames(),
+            end_frame: chip_eight.frames(),
+            checksum_interval: checksum_interval.max(1),
+            events: Vec::new(),
+            checksums: Vec::new(),
+        };
+    }
+    pub fn key_changed(&mut self, frame: u64, key: Key, position: Position) {
+        self.events.push(KeyChange { frame, key, position });
+    }
+    // Called as each frame ends.
+    pub fn frame_ended(&mut self, chip_eight: &ChipEight) {
+        self.end_frame = chip_eight.frames();
+        if (self.end_frame - self.start_frame) % self.checksum_interval == 0 {
+            self.checksums.push((self.end_frame, checksum(chip_eight)));
+        }
+    }
+    // Close the movie off with a checksum of the final frame.
+    pub fn finish(&mut self, chip_eight: &ChipEight) {
+        self.end_frame = chip_eight.frames();
+        if self.checksums.last().map_or(true, |(frame, _)| *frame != self.end_frame) {
+            self.checksums.push((self.end_frame, checksum(chip_eight)));
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer: StateWriter = StateWriter::empty();
+        writer.put_bytes(MAGIC);
+        writer.put_u8(VERSION);
+        writer.put_bytes(&self.rom_sha1);
+        writer.put_u64(self.seed);
+        writer.put_u8(self.random_mode.id());
+        match self.speed {
+            Speed::InstructionsPerSecond(instructions) => {
+                writer.put_u8(0);
+                writer.put_u32(instructions);
+            }
+            Speed::InstructionsPerFrame(instructions) => {
+                writer.put_u8(1);
+                writer.put_u32(instructions);
+            }
+        }
+        self.quirks.save(&mut writer);
+        writer.put_u32(self.start_state.len() as u32);
+        writer.put_bytes(&self.start_state);
+        writer.put_u64(self.start_frame);
+        writer.put_u64(self.end_frame);
+        writer.put_u64(self.checksum_interval);
+        writer.put_u32(self.events.len() as u32);
+        for event in &self.events {
+            writer.put_u64(event.frame);
+            writer.put_u8(event.key.index());
+            writer.put_bool(event.position == Position::DOWN);
+        }
+        writer.put_u32(self.checksums.len() as u32);
+        for (frame, checksum) in &self.checksums {
+            writer.put_u64(*frame);
+            writer.put_u32(*checksum);
+        }
+        return writer.finish();
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, SaveStateError> {
+        let mut reader: StateReader = StateReader::raw(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version: u8 = reader.get_u8()?;
+        if version != VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        let mut rom_sha1: [u8; 20] = [0; 20];
+        rom_sha1.copy_from_slice(reader.take(20)?);
+        let seed: u64 = reader.get_u64()?;
+        let random_mode: RandomMode =
+            RandomMode::from_id(reader.get_u8()?).ok_or(SaveStateError::Corrupt)?;
+        let speed: Speed = match reader.get_u8()? {
+            0 => Speed::InstructionsPerSecond(reader.get_u32()?),
+            1 => Speed::InstructionsPerFrame(reader.get_u32()?),
+            _ => return Err(SaveStateError::Corrupt),
+        };
+        let mut quirks: Quirks = Quirks::new();
+        quirks.load(&mut reader)?;
+        let length: usize = reader.get_u32()? as usize;
+        let start_state: Vec<u8> = reader.take(length)?.to_vec();
+        let start_frame: u64 = reader.get_u64()?;
+        let end_frame: u64 = reader.get_u64()?;
+        let checksum_interval: u64 = reader.get_u64()?;
+        if checksum_interval == 0 {
+            return Err(SaveStateError::Corrupt);
+        }
+
+        let mut events: Vec<KeyChange> = Vec::new();
+        for _ in 0..reader.get_u32()? {
+            let frame: u64 = reader.get_u64()?;
+            let key: Key = Key::from_index(reader.get_u8()?).ok_or(SaveStateError::Corrupt)?;
+            let position: Position = if reader.get_bool()? {Position::DOWN} else {Position::UP};
+            events.push(KeyChange { frame, key, position });
+        }
+        let mut checksums: Vec<(u64, u32)> = Vec::new();
+        for _ in 0..reader.get_u32()? {
+            checksums.push((reader.get_u64()?, reader.get_u32()?));
+        }
+        reader.finish()?;
+        return Ok(Movie {
+            rom_sha1, seed, random_mode, speed, quirks,
+            start_state, start_frame, end_frame, checksum_interval, events, checksums,
+        });
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaybackResult {
+    // Every checksum matched.
+    Completed { frames: u64 },
+    // The state after `frame` is not the one recorded, it went wrong somewhere after
+    // `last_good_frame`.
+    Desync { frame: u64, last_good_frame: u64, expected: u32, actual: u32 },
+    Fault { frame: u64, error: EmulatorError },
+}
+
+impl fmt::Display for PlaybackResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlaybackResult::Completed { frames } =>
+                write!(f, "played back {} frames in sync", frames),
+            PlaybackResult::Desync { frame, last_good_frame, expected, actual } =>
+                write!(f, "desync at frame {} (last in sync at frame {}): expected checksum \
+                    {:08X}, got {:08X}", frame, last_good_frame, expected, actual),
+            PlaybackResult::Fault { frame, error } =>
+                write!(f, "fault during frame {}: {}", frame, error),
+        }
+    }
+}
+
+// Replay a movie on a machine that already has its ROM loaded. Set the pacing beforehand, turbo
+// to check a movie, real time to watch it.
+pub fn play(chip_eight: &mut ChipEight, movie: &Movie) -> Result<PlaybackResult, SaveStateError> {
+    if chip_eight.rom_sha1() != movie.rom_sha1 {
+        return Err(SaveStateError::RomMismatch);
+    }
+    chip_eight.set_seed(movie.random_mode, movie.seed);
+    chip_eight.set_speed(movie.speed);
+    chip_eight.set_quirks(movie.quirks);
+    chip_eight.load_state(&movie.start_state)?;
+
+    let mut events = movie.events.iter().peekable();
+    let mut checksums = movie.checksums.iter().peekable();
+    let mut last_good_frame: u64 = movie.start_frame;
+    while chip_eight.frames() < movie.end_frame {
+        let frame: u64 = chip_eight.frames();
+        while let Some(event) = events.next_if(|event| event.frame <= frame) {
+            chip_eight.queue_key(event.key, event.position);
+        }
+        if let Err(error) = chip_eight.run_frame() {
+            return Ok(PlaybackResult::Fault { frame, error });
+        }
+        let frame: u64 = chip_eight.frames();
+        if let Some((_, expected)) = checksums.next_if(|(at, _)| *at <= frame) {
+            let actual: u32 = checksum(chip_eight);
+            if actual != *expected {
+                return Ok(PlaybackResult::Desync {
+                    frame, last_good_frame, expected: *expected, actual,
+                });
+            }
+            last_good_frame = frame;
+        }
+    }
+    return Ok(PlaybackResult::Completed { frames: movie.end_frame - movie.start_frame });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::builder::ChipEightBuilder;
+    use super::super::scheduler::Pacing;
+
+    // CXNN into the delay timer and a key check, forever.
+    const ROM: [u8; 10] = [0xC0, 0xFF, 0xF0, 0x15, 0xE1, 0x9E, 0x12, 0x00, 0x12, 0x00];
+
+    fn machine() -> ChipEight {
+        let mut chip_eight: ChipEight =
+            ChipEightBuilder::new().instructions_per_second(700).pacing(Pacing::Turbo).build();
+        chip_eight.load_rom(&ROM).unwrap();
+        return chip_eight;
+    }
+
+    fn run_frames(chip_eight: &mut ChipEight, frames: u64) {
+        for _ in 0..frames {
+            chip_eight.run_frame().unwrap();
+        }
+    }
+
+    #[test]
+    fn movies_restarted_by_a_state_load_stay_in_sync() {
+        let mut chip_eight: ChipEight = machine();
+        chip_eight.start_recording(1);
+        // One frame at 700 per second leaves instructions owed to the next.
+        run_frames(&mut chip_eight, 1);
+        let state: Vec<u8> = chip_eight.save_state();
+        run_frames(&mut chip_eight, 7);
+        chip_eight.load_state(&state).unwrap();
+        chip_eight.queue_key(Key::from_index(0).unwrap(), Position::DOWN);
+        run_frames(&mut chip_eight, 5);
+        chip_eight.queue_key(Key::from_index(0).unwrap(), Position::UP);
+        run_frames(&mut chip_eight, 5);
+        let movie: Movie = chip_eight.stop_recording().unwrap();
+        assert_eq!(movie.events.len(), 2);
+
+        let movie: Movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(play(&mut machine(), &movie), Ok(PlaybackResult::Completed { frames: 10 }));
+    }
+}
@@ -1,9 +1,17 @@
+use std::collections::VecDeque;
+
 use crate::common::hex_utils;
 
-use super::api::Tick;
+use super::api::{MachineState, StepOutcome, Tick};
+use super::crash_report::CrashReport;
+use super::error::EmulatorError;
 use super::memory::Memory;
 use super::graphics::Graphics;
-use super::keypad::{Keypad, Position};
+use super::keypad::{Key, KeyEvent, Keypad, KeyWaitMode, Position};
+use super::instruction::{self, Instruction};
+use super::quirks::{IndexIncrement, Quirks};
+use super::random::{RandomMode, RandomSource};
+use super::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 use super::sound::Sound;
 
 
@@ -26,9 +34,18 @@ pub struct Processor {
     // Misc
     waiting: bool,
     key_register: usize,
+    // Key seen going down while waiting for it to be released.
+    held_key: Option<Key>,
+    // DXYN drew this frame and the display-wait quirk holds the CPU until the next one.
+    waiting_for_frame: bool,
+    quirks: Quirks,
+    random: Box<dyn RandomSource>,
+    // (address, opcode) of the last few instructions executed, for crash reports.
+    history: VecDeque<(u16, u16)>,
 }
 
 const F_REGISTER_POINTER: usize = 0xF;
+const HISTORY_LENGTH: usize = 16;
 
 impl Processor {
     pub fn new(memory: Memory, graphics: Graphics, keypad: Keypad, sound: Sound) -> Processor {
@@ -48,124 +65,212 @@ impl Processor {
 
             waiting: false,
             key_register: 0,
+            held_key: None,
+            waiting_for_frame: false,
+            quirks: Quirks::new(),
+            random: RandomMode::Xorshift.source(0),
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
         }
     }
-    pub fn init(&mut self, rom: &Vec<u8>) -> () {
-        self.memory.load(&rom);
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.graphics.set_clipping(quirks.clip_sprites);
+    }
+    pub fn set_random_source(&mut self, random: Box<dyn RandomSource>) {
+        self.random = random;
+    }
+    pub fn init(&mut self, rom: &[u8]) -> Result<(), EmulatorError> {
+        return self.memory.load(rom);
     }
     // Execute the current opcode
-    fn execute(&mut self) -> () {
-        return match self.opcode & 0xF000 {
-            0x0000 => self.parse_end_function(),
-            0x1000 => self.jump(),
-            0x2000 => self.call(),
-            0x3000 => self.skip_on_equal(),
-            0x4000 => self.skip_on_not_equal(),
-            0x5000 => self.skip_if_registers(),
-            0x6000 => self.load_into_register(),
-            0x7000 => self.add_to_register(),
-            0x8000 => self.parse_register_operation(),
-            0x9000 => self.skip_if_not_regsiters(),
-            0xA000 => self.load_into_index_register(),
-            0xB000 => self.jump_plus_v0(),
-            0xC000 => self.assign_random(),
-            0xD000 => {
+    fn execute(&mut self) -> Result<Instruction, EmulatorError> {
+        let invalid_opcode: EmulatorError =
+            EmulatorError::InvalidOpcode { pc: self.program_counter - 2, opcode: self.opcode };
+        let instruction: Instruction =
+            instruction::decode(self.opcode).map_err(|_| invalid_opcode)?;
+        match instruction {
+            // Machine code routines can't be run, there is no 1802 to run them on.
+            Instruction::Sys(_) => return Err(invalid_opcode),
+            Instruction::Cls => self.graphics.clear(),
+            Instruction::Ret => self.subroutine_return()?,
+            Instruction::Jp(address) => self.jump(address),
+            Instruction::Call(address) => self.call(address)?,
+            Instruction::SeVxByte { x, kk } => self.skip_on_equal(x, kk),
+            Instruction::SneVxByte { x, kk } => self.skip_on_not_equal(x, kk),
+            Instruction::SeVxVy { x, y } => self.skip_if_registers(x, y),
+            Instruction::LdVxByte { x, kk } => self.load_into_register(x, kk),
+            Instruction::AddVxByte { x, kk } => self.add_to_register(x, kk),
+            Instruction::LdVxVy { x, y } => self.copy_register(x, y),
+            Instruction::Or { x, y } => self.or_registers(x, y),
+            Instruction::And { x, y } => self.and_registers(x, y),
+            Instruction::Xor { x, y } => self.xor_registers(x, y),
+            Instruction::AddVxVy { x, y } => self.add_registers(x, y),
+            Instruction::Sub { x, y } => self.sub_registers(x, y),
+            Instruction::Shr { x, y } => self.div_by_two(x, y),
+            Instruction::Subn { x, y } => self.sub_reverse_registers(x, y),
+            Instruction::Shl { x, y } => self.multiply_by_two(x, y),
+            Instruction::SneVxVy { x, y } => self.skip_if_not_registers(x, y),
+            Instruction::LdI(address) => self.set_index_register(address),
+            Instruction::JpV0(address) => self.jump_plus_v0(address),
+            Instruction::Rnd { x, kk } => self.assign_random(x, kk),
+            Instruction::Drw { x, y, n } => self.draw_sprite(x, y, n)?,
+            Instruction::Skp { x } => self.skip_on_key(x, Position::DOWN),
+            Instruction::Sknp { x } => self.skip_on_key(x, Position::UP),
+            Instruction::LdVxDt { x } => self.set_delay_timer_to_register(x),
+            Instruction::LdVxK { x } => self.wait(x),
+            Instruction::LdDtVx { x } => self.set_delay_timer(x),
+            Instruction::LdStVx { x } => self.sound.set_sound_timer(self.registers[x]),
+            Instruction::AddIVx { x } => self.add_register_to_i(x),
+            Instruction::LdFVx { x } => {
+                let hex_location: u8 = self.memory.get_hex_sprite_index(self.registers[x]);
+                self.set_index_register(hex_location as u16);
+            }
+            Instruction::LdBVx { x } => self.store_bcd(x)?,
+            Instruction::LdIVx { x } => {
+                let bytes: Vec<u8> = self.get_values_upto_register(x);
+                let starting_index: usize = self.index_register as usize;
+                self.memory.set_bytes(starting_index, bytes)?;
+                self.increment_index_after_load_store(x);
+            }
+            Instruction::LdVxI { x } => {
                 let starting_index: u16 = self.index_register;
-                let (x_coordinate, y_coordinate) = self.get_opcode_register_values();
-
-                let number_of_bytes: u8 = self.get_opcode_nibble();
-                let bytes: Vec<u8> = self.memory.get_bytes(starting_index, number_of_bytes);
-                self.graphics.draw(bytes, x_coordinate, y_coordinate);
+                let bytes: Vec<u8> = self.memory.get_bytes(starting_index, x as u8 + 1)?;
+                self.set_values_upto_register(bytes);
+                self.increment_index_after_load_store(x);
             }
-            0xE000 => self.parse_keyboard_operation(),
-            0xF000 => self.parse_util_function(),
-            _ => panic!("Invalide opcode for general parsing")
         };
+        return Ok(instruction);
     }
-    fn parse_end_function(&mut self) -> () {
-        if self.opcode == 0x00E0 {
-            self.graphics.clear();
-        } else if self.opcode == 0x00EE {
-            self.subroutine_return();
-        } else {
-            panic!("Invalid subopcode for end function");
+    // Fetch and execute one instruction, unless FX0A or the display wait is holding the CPU.
+    pub fn step(&mut self) -> Result<StepOutcome, EmulatorError> {
+        if self.waiting_for_frame {
+            return Ok(StepOutcome::WaitingForFrame);
         }
-
-    }
-    fn parse_register_operation(&mut self) -> () {
-        return match 0x000F & self.opcode {
-            0x0 => self.copy_register(),
-            0x1 => self.or_registers(),
-            0x2 => self.and_registers(),
-            0x3 => self.xor_registers(),
-            0x4 => self.add_registers(),
-            0x5 => self.sub_registers(),
-            0x6 => self.div_by_two(),
-            0x7 => self.sub_reverse_registers(),
-            0xE => self.multiply_by_two(),
-            _ => panic!("Invalid subopcode for register operation"),
+        // The CPU stops fetching while FX0A is pending, but the timers keep running.
+        if self.waiting {
+            return Ok(StepOutcome::WaitingForKey);
         }
-    }
-    fn parse_keyboard_operation(&mut self) -> () {
-        let subopcode = self.opcode & 0x00FF;
-        if subopcode != 0x009E || subopcode != 0x00A1 {
-            panic!("Invalid subopcode for keyboard operation");
+        let pc: u16 = self.program_counter;
+        self.opcode = self.memory.get_opcode(pc as usize)?;
+        self.increment_program_counter();
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
         }
-
-        let register_index: usize = hex_utils::right_shift(self.opcode & 0x0F00, 2) as usize;
-        let key = self.registers[register_index];
-        if (subopcode == 0x009E && self.keypad.get_key(key) == Position::DOWN) ||
-                (subopcode == 0x00A1 &&self.keypad.get_key(key) == Position::UP) {
-            self.increment_program_counter();
+        self.history.push_back((pc, self.opcode));
+        let instruction: Instruction = self.execute()?;
+        return Ok(StepOutcome::Executed { pc, instruction });
+    }
+    pub fn crash_report(&self, error: EmulatorError) -> CrashReport {
+        CrashReport {
+            error,
+            registers: self.registers,
+            index_register: self.index_register,
+            program_counter: self.program_counter,
+            stack: self.stack[..self.stack_pointer].to_vec(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound.sound_timer(),
+            history: self.history.iter().copied().collect(),
+            memory: self.memory.as_slice().to_vec(),
+            rom_sha1: None,
         }
     }
-    fn parse_util_function(&mut self) -> () {
-        let register_index: usize = hex_utils::right_shift(self.opcode & 0x0F00, 2) as usize;
-        match self.opcode & 0x00FF {
-            0x0007 => self.set_delay_timer_to_register(register_index),
-            0x000A => self.wait(register_index),
-            0x0015 => self.set_delay_timer(register_index),
-            0x0018 => {
-                let register_value: u8 = self.registers[register_index];
-                self.sound.set_sound_timer(register_value);
-            }
-            0x001E => self.add_register_to_i(register_index),
-            0x0029 => {
-                let register_value: u8 = self.registers[register_index];
-                let hex_location: u8 = self.memory.get_hex_sprite_index(register_value);
-                self.set_index_register(hex_location as u16);
-            }
-            0x0033 => {
-                let register_value: u8 = self.registers[register_index];
-                let starting_index: usize = self.index_register as usize;
-                let bcd: [u8; 3] = [
-                    register_value / 100, // Mod not required, u8 cannot go past 255
-                    register_value / 10 % 10,
-                    register_value % 10, // Div not required, only need ones place
-                ];
-                self.memory.set_bytes(starting_index, bcd.to_vec());
-            }
-            0x0055 => {
-                let bytes: Vec<u8> = self.get_values_upto_register(register_index);
-                let starting_index: usize = self.index_register as usize;
-                self.memory.set_bytes(starting_index, bytes);
-            }
-            0x0065 => {
-                let starting_index: u16 = self.index_register;
-                let bytes: Vec<u8> = self.memory.get_bytes(starting_index, register_index as u8);
-                self.set_values_upto_register(bytes);
-            }
-            _ => panic!(),
+    pub fn program_counter(&self) -> u16 {
+        return self.program_counter;
+    }
+    pub fn state(&self) -> MachineState<'_> {
+        MachineState {
+            registers: self.registers,
+            index_register: self.index_register,
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            stack: &self.stack[..self.stack_pointer],
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound.sound_timer(),
+            waiting_for_key: if self.waiting {Some(self.key_register)} else {None},
+            opcode: self.opcode,
+            memory: self.memory.as_slice(),
         }
     }
-    pub fn load(&mut self, program: &Vec<u8>) {
-        self.memory.load(program);
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        return &mut self.memory;
+    }
+    pub fn poke(&mut self, address: u16, bytes: &[u8]) -> Result<(), EmulatorError> {
+        return self.memory.set_bytes(address as usize, bytes.to_vec());
+    }
+    pub fn graphics(&self) -> &Graphics {
+        return &self.graphics;
+    }
+    pub fn graphics_mut(&mut self) -> &mut Graphics {
+        return &mut self.graphics;
+    }
+    pub fn quirks(&self) -> Quirks {
+        return self.quirks;
+    }
+    pub fn keypad(&self) -> &Keypad {
+        return &self.keypad;
+    }
+    pub fn keypad_mut(&mut self) -> &mut Keypad {
+        return &mut self.keypad;
+    }
+    pub fn sound_timer(&self) -> u8 {
+        return self.sound.sound_timer();
+    }
+    // Count the delay and sound timers down, called at 60 Hz.
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        self.sound.tick();
+        self.random.tick_frame();
+        self.waiting_for_frame = false;
+    }
+    pub fn is_waiting(&self) -> bool {
+        return self.waiting;
+    }
+    pub fn is_waiting_for_frame(&self) -> bool {
+        return self.waiting_for_frame;
     }
     pub fn wait(&mut self, register_index: usize) {
+        self.waiting = true;
         self.key_register = register_index;
+        self.held_key = None;
+    }
+    pub fn end_wait(&mut self, key: Key) {
+        self.waiting = false;
+        self.held_key = None;
+        self.set_register_value(self.key_register, key.index());
+    }
+    // Called at each frame boundary: bring the keypad up to date with the frontend, then let a
+    // pending FX0A look at what changed.
+    pub fn apply_key_events(&mut self) -> Vec<KeyEvent> {
+        let events: Vec<KeyEvent> = self.keypad.apply_events();
+        if self.waiting {
+            self.poll_wait();
+        }
+        return events;
     }
-    pub fn end_wait(&mut self, key: u8) {
-        self.set_register_value(self.key_register, key);
+    // Only keys that go down after FX0A started count, a key already held does not.
+    fn poll_wait(&mut self) {
+        if self.held_key.is_none() {
+            self.held_key = self.keypad.first_just_pressed();
+        }
+        match (self.quirks.key_wait, self.held_key) {
+            (_, None) => {}
+            (KeyWaitMode::Press, Some(key)) => self.end_wait(key),
+            // A tap within a single frame has come back up already.
+            (KeyWaitMode::Release, Some(key)) => {
+                if self.keypad.just_released(key) || !self.keypad.is_down(key) {
+                    self.end_wait(key);
+                }
+            }
+        }
+    }
+    fn increment_index_after_load_store(&mut self, register_index: usize) {
+        self.index_register = self.index_register.wrapping_add(match self.quirks.index_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => register_index as u16,
+            IndexIncrement::ByXPlusOne => register_index as u16 + 1,
+        });
     }
     fn increment_program_counter(&mut self) {
         self.program_counter += 2; // Two bytes per instruction
@@ -184,162 +289,459 @@ impl Processor {
     pub fn set_index_register(&mut self, value: u16) -> () {
         self.index_register = value;
     }
-    pub fn get_opcode_register_values(&mut self) -> (usize, usize) {
-        return self.dehydrate_registers();
-    }
-    pub fn get_opcode_nibble(&mut self) -> u8 {
-        return 0x000F & self.opcode as u8;
-    }
     pub fn set_delay_timer_to_register(&mut self, register_index: usize) -> () {
         self.registers[register_index] = self.delay_timer;
     }
     pub fn set_delay_timer(&mut self, register_index: usize) -> () {
         self.delay_timer = self.registers[register_index];
     }
-    pub fn subroutine_return(&mut self) {
-        // Set the PC to the address at the top of the stack and decrement stack by one.
-        self.program_counter = self.stack[self.stack_pointer];
+    pub fn subroutine_return(&mut self) -> Result<(), EmulatorError> {
+        // Pop the address at the top of the stack into the PC.
+        if self.stack_pointer == 0 {
+            return Err(EmulatorError::StackUnderflow { pc: self.program_counter - 2 });
+        }
         self.stack_pointer -= 1;
+        self.program_counter = self.stack[self.stack_pointer];
+        return Ok(());
     }
-    pub fn jump(&mut self) {
-        // Jump to the address of the last 12 bits of the opcode
-        self.program_counter = self.dehydrate_opcode();
+    pub fn jump(&mut self, address: u16) {
+        self.program_counter = address;
     }
-    pub fn jump_plus_v0(&mut self) {
-        // Jump to the address of the last 12 bits of the opcode
-        self.program_counter = self.dehydrate_opcode() + self.registers[0] as u16;
+    pub fn jump_plus_v0(&mut self, address: u16) {
+        // Offset by V0, or by VX (the high nibble of the address) with the jump quirk
+        let offset_register: usize =
+            if self.quirks.jump_uses_vx {hex_utils::right_shift(address, 2) as usize} else {0};
+        self.program_counter = address + self.registers[offset_register] as u16;
     }
-    pub fn assign_random(&mut self) {
-        let (register_pointer, value) = self.dehydrate_register_and_value();
-        self.registers[register_pointer] = value & hex_utils::random_byte();
+    pub fn assign_random(&mut self, register_pointer: usize, value: u8) {
+        self.registers[register_pointer] = value & self.random.next_byte();
     }
-    pub fn call(&mut self) {
+    pub fn call(&mut self, address: u16) -> Result<(), EmulatorError> {
         // Store current address in the PC to the top of the stack and set the PC to the new addr
+        self.push_stack(self.program_counter)?;
+        self.program_counter = address;
+        return Ok(());
+    }
+    pub fn push_stack(&mut self, address: u16) -> Result<(), EmulatorError> {
+        if self.stack_pointer == self.stack.len() {
+            // Blame the instruction just fetched, if a tool rather than CALL filled the stack.
+            return Err(EmulatorError::StackOverflow { pc: self.program_counter.saturating_sub(2) });
+        }
+        self.stack[self.stack_pointer] = address;
         self.stack_pointer += 1;
-        self.stack[self.stack_pointer] = self.program_counter;
-        self.program_counter = self.dehydrate_opcode();
+        return Ok(());
     }
     // TODO: refactor these skip ifs into something magical if possible
-    pub fn skip_on_equal(&mut self) {
-        let (register_pointer, value) = self.dehydrate_register_and_value();
+    pub fn skip_on_equal(&mut self, register_pointer: usize, value: u8) {
         if self.registers[register_pointer] == value {
             self.increment_program_counter();
         }
     }
-    pub fn skip_on_not_equal(&mut self) {
-        let (register_pointer, value) = self.dehydrate_register_and_value();
+    pub fn skip_on_not_equal(&mut self, register_pointer: usize, value: u8) {
         if self.registers[register_pointer] != value {
             self.increment_program_counter();
         }
     }
-    pub fn load_into_register(&mut self) {
-        let (register_pointer, value) = self.dehydrate_register_and_value();
+    pub fn skip_on_key(&mut self, register_pointer: usize, position: Position) {
+        let key: Key = Key::ALL[(self.registers[register_pointer] & 0xF) as usize];
+        if self.keypad.get_key(key) == position {
+            self.increment_program_counter();
+        }
+    }
+    pub fn load_into_register(&mut self, register_pointer: usize, value: u8) {
         self.registers[register_pointer] = value;
     }
-    pub fn copy_register(&mut self) {
-        let (x_register, y_register) = self.dehydrate_registers();
+    pub fn copy_register(&mut self, x_register: usize, y_register: usize) {
         self.registers[x_register] = self.registers[y_register];
     }
-    pub fn or_registers(&mut self) {
-        let (x_register, y_register) = self.dehydrate_registers();
+    pub fn or_registers(&mut self, x_register: usize, y_register: usize) {
         self.registers[x_register] |= self.registers[y_register];
+        self.reset_flag_after_logic();
     }
-    pub fn and_registers(&mut self) {
-        let (x_register, y_register) = self.dehydrate_registers();
+    pub fn and_registers(&mut self, x_register: usize, y_register: usize) {
         self.registers[x_register] &= self.registers[y_register];
+        self.reset_flag_after_logic();
     }
-    pub fn xor_registers(&mut self) {
-        let (x_register, y_register) = self.dehydrate_registers();
+    pub fn xor_registers(&mut self, x_register: usize, y_register: usize) {
         self.registers[x_register] ^= self.registers[y_register];
+        self.reset_flag_after_logic();
     }
-    pub fn add_registers(&mut self) {
-        let (x_register, y_register) = self.dehydrate_registers();
-        let sum: u16 = self.registers[x_register] as u16
-            + self.registers[y_register] as u16;
+    fn reset_flag_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.registers[F_REGISTER_POINTER] = 0;
+        }
+    }
+    // The ALU operations below write VF after the result, so VF as the destination ends up
+    // holding the flag.
+    pub fn add_registers(&mut self, x_register: usize, y_register: usize) {
+        let (sum, carry) = self.registers[x_register].overflowing_add(self.registers[y_register]);
 
-        self.registers[F_REGISTER_POINTER] = if sum > 0x00FF {1} else {0};
-        self.registers[x_register] = sum as u8;
+        self.registers[x_register] = sum;
+        self.registers[F_REGISTER_POINTER] = if carry {1} else {0};
     }
     pub fn add_register_to_i(&mut self, register_index: usize) {
-        self.index_register += self.registers[register_index] as u16;
+        let value: u16 = self.registers[register_index] as u16;
+        self.index_register = self.index_register.wrapping_add(value);
     }
-    pub fn sub_registers(&mut self) {
-        let (x_register, y_register) = self.dehydrate_registers();
-        self.registers[F_REGISTER_POINTER] = 
-            if self.registers[x_register] < self.registers[y_register]
-                {1} else {0};
+    pub fn sub_registers(&mut self, x_register: usize, y_register: usize) {
+        // VF is set when there is no borrow
+        let (difference, borrow) =
+            self.registers[x_register].overflowing_sub(self.registers[y_register]);
 
-        self.registers[x_register] -= self.registers[y_register];
+        self.registers[x_register] = difference;
+        self.registers[F_REGISTER_POINTER] = if borrow {0} else {1};
     }
-    pub fn sub_reverse_registers(&mut self) {
-        let (x_register, y_register) = self.dehydrate_registers();
-        self.registers[F_REGISTER_POINTER] = 
-            if self.registers[x_register] > self.registers[y_register]
-                {1} else {0};
+    pub fn sub_reverse_registers(&mut self, x_register: usize, y_register: usize) {
+        let (difference, borrow) =
+            self.registers[y_register].overflowing_sub(self.registers[x_register]);
 
-        self.registers[x_register] = self.registers[y_register] -
-            self.registers[x_register];
+        self.registers[x_register] = difference;
+        self.registers[F_REGISTER_POINTER] = if borrow {0} else {1};
     }
-    pub fn div_by_two(&mut self) {
-        let (x_register, _y_register) = self.dehydrate_registers();
-        self.registers[F_REGISTER_POINTER] = if 0b00000001 & self.registers[x_register] == 0x01
-            {1} else {0};
+    pub fn div_by_two(&mut self, x_register: usize, y_register: usize) {
+        self.shift_source(x_register, y_register);
+        let shifted_out: u8 = self.registers[x_register] & 0b00000001;
 
-        self.registers[x_register] /= 2;
+        self.registers[x_register] >>= 1;
+        self.registers[F_REGISTER_POINTER] = shifted_out;
     }
-    pub fn multiply_by_two(&mut self) {
-        let (x_register, _y_register) = self.dehydrate_registers();
-        self.registers[F_REGISTER_POINTER] = if 0b10000000 & self.registers[x_register] == 0xF0
-            {1} else {0};
+    pub fn multiply_by_two(&mut self, x_register: usize, y_register: usize) {
+        self.shift_source(x_register, y_register);
+        let shifted_out: u8 = (self.registers[x_register] & 0b10000000) >> 7;
 
-        self.registers[x_register] *= 2;
-    }
-    pub fn load_into_index_register(&mut self) {
-        self.index_register = self.dehydrate_opcode();
-    }
-    pub fn add_to_register(&mut self) {
-        let (register_pointer, value) = self.dehydrate_register_and_value();
-        self.registers[register_pointer] += value;
+        self.registers[x_register] <<= 1;
+        self.registers[F_REGISTER_POINTER] = shifted_out;
+    }
+    pub fn draw_sprite(&mut self, x_register: usize, y_register: usize, number_of_bytes: u8)
+            -> Result<(), EmulatorError> {
+        let starting_index: u16 = self.index_register;
+        let x_coordinate: usize = self.registers[x_register] as usize;
+        let y_coordinate: usize = self.registers[y_register] as usize;
+
+        let bytes: Vec<u8> = self.memory.get_bytes(starting_index, number_of_bytes)?;
+        let pixels_erased: bool = self.graphics.draw(&bytes, x_coordinate, y_coordinate);
+        self.registers[F_REGISTER_POINTER] = if pixels_erased {1} else {0};
+        self.waiting_for_frame = self.quirks.display_wait;
+        return Ok(());
+    }
+    // The original interpreter shifted VY into VX, later ones shift VX in place.
+    fn shift_source(&mut self, x_register: usize, y_register: usize) {
+        if self.quirks.shift_uses_vy {
+            self.registers[x_register] = self.registers[y_register];
+        }
     }
-    pub fn skip_if_registers(&mut self) {
-        let (x_register, y_register) = self.dehydrate_registers();
+    pub fn store_bcd(&mut self, register_index: usize) -> Result<(), EmulatorError> {
+        let register_value: u8 = self.registers[register_index];
+        let starting_index: usize = self.index_register as usize;
+        let bcd: [u8; 3] = [
+            register_value / 100, // Mod not required, u8 cannot go past 255
+            register_value / 10 % 10,
+            register_value % 10, // Div not required, only need ones place
+        ];
+        return self.memory.set_bytes(starting_index, bcd.to_vec());
+    }
+    pub fn add_to_register(&mut self, register_pointer: usize, value: u8) {
+        // No carry flag for 7XNN, it just wraps
+        self.registers[register_pointer] = self.registers[register_pointer].wrapping_add(value);
+    }
+    pub fn skip_if_registers(&mut self, x_register: usize, y_register: usize) {
         if self.registers[x_register] == self.registers[y_register] {
             self.increment_program_counter();
         }
     }
-    pub fn skip_if_not_regsiters(&mut self) {
-        let (x_register, y_register) = self.dehydrate_registers();
+    pub fn skip_if_not_registers(&mut self, x_register: usize, y_register: usize) {
         if self.registers[x_register] != self.registers[y_register] {
             self.increment_program_counter();
         }
     }
-    // Dehydration BEGIN
-    // TODO: dehydrate the mains, nnn, xkk, and xy0..9. Also, come up with better names if possible.
-    fn dehydrate_opcode(&mut self) -> u16 {
-        return hex_utils::left_pad(self.opcode, 1);
+}
+
+impl SaveState for Processor {
+    fn save(&self, writer: &mut StateWriter) -> () {
+        writer.put_u16(self.opcode);
+        writer.put_bytes(&self.registers);
+        writer.put_u16(self.index_register);
+        writer.put_u16(self.program_counter);
+        for address in self.stack {
+            writer.put_u16(address);
+        }
+        writer.put_u8(self.stack_pointer as u8);
+        writer.put_u8(self.delay_timer);
+        writer.put_bool(self.waiting);
+        writer.put_u8(self.key_register as u8);
+        writer.put_u8(self.held_key.map_or(0xFF, Key::index));
+        writer.put_bool(self.waiting_for_frame);
+        self.quirks.save(writer);
+        writer.put_u64(self.random.state());
+
+        self.memory.save(writer);
+        self.graphics.save(writer);
+        self.keypad.save(writer);
+        self.sound.save(writer);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.opcode = reader.get_u16()?;
+        self.registers.copy_from_slice(reader.take(16)?);
+        self.index_register = reader.get_u16()?;
+        self.program_counter = reader.get_u16()?;
+        for address in self.stack.iter_mut() {
+            *address = reader.get_u16()?;
+        }
+        self.stack_pointer = reader.get_u8()? as usize;
+        self.delay_timer = reader.get_u8()?;
+        self.waiting = reader.get_bool()?;
+        self.key_register = reader.get_u8()? as usize;
+        self.held_key = match reader.get_u8()? {
+            0xFF => None,
+            key => Some(Key::from_index(key).ok_or(SaveStateError::Corrupt)?),
+        };
+        self.waiting_for_frame = reader.get_bool()?;
+        if self.stack_pointer > self.stack.len() || self.key_register > F_REGISTER_POINTER {
+            return Err(SaveStateError::Corrupt);
+        }
+        let mut quirks: Quirks = self.quirks;
+        quirks.load(reader)?;
+        self.set_quirks(quirks);
+        self.random.set_state(reader.get_u64()?);
+
+        SaveState::load(&mut self.memory, reader)?;
+        self.graphics.load(reader)?;
+        self.keypad.load(reader)?;
+        self.sound.load(reader)?;
+        // The history is about how we got to the saved state, which is no longer the case.
+        self.history.clear();
+        return Ok(());
     }
-    fn dehydrate_register_and_value(&mut self) -> (usize, u8) {
-        let opcode_data: u16 = self.dehydrate_opcode();
-        let register_pointer: usize = hex_utils::right_shift(opcode_data, 2) as usize;
-        let value: u8 = hex_utils::left_pad(opcode_data, 2) as u8;
-        return (register_pointer, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use crate::chip_eight_core::api::Region;
+    use crate::chip_eight_core::graphics::{HEIGHT, WIDTH};
+
+    fn processor(rom: &[u8], quirks: Quirks) -> Processor {
+        let mut processor: Processor =
+            Processor::new(Memory::new(), Graphics::new(), Keypad::new(), Sound::new());
+        processor.set_quirks(quirks);
+        processor.init(rom).unwrap();
+        return processor;
     }
-    fn dehydrate_registers(&mut self) -> (usize, usize) {
-        let opcode_data: u16 = self.dehydrate_opcode();
-        let x_register: usize = hex_utils::right_shift(opcode_data, 3) as usize;
-        let y_register: usize = hex_utils::right_shift(opcode_data, 2) as usize;
-        return (x_register, y_register);
+
+    fn run(processor: &mut Processor, instructions: usize) {
+        for _ in 0..instructions {
+            processor.step().unwrap();
+        }
     }
-    // Dehydration END
-}
 
-impl Tick for Processor {
-    fn tick(&mut self) {
-        self.opcode = self.memory.get_opcode(self.program_counter as usize);
-        self.increment_program_counter();
-        self.execute();
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
+    fn lit(processor: &Processor, x: usize, y: usize) -> bool {
+        let everything: Region = Region { x: 0, y: 0, width: WIDTH, height: HEIGHT };
+        return processor.graphics().frame(everything).pixel(x, y);
+    }
+
+    #[test]
+    fn draw_takes_coordinates_from_vx_and_vy() {
+        // V3 = 10, V7 = 5, I at the font's 0, then DRW V3, V7, 1.
+        let mut processor: Processor =
+            processor(&[0x63, 0x0A, 0x67, 0x05, 0xA0, 0x00, 0xD3, 0x71], Quirks::new());
+        run(&mut processor, 4);
+        // The top row of the 0 is 0xF0.
+        assert!((10..14).all(|x| lit(&processor, x, 5)));
+        assert!(!lit(&processor, 14, 5));
+        assert!(!lit(&processor, 0, 0));
+        assert_eq!(processor.state().registers[0xF], 0);
+    }
+
+    #[test]
+    fn drawing_over_lit_pixels_sets_vf() {
+        // The same sprite twice at V1, V2, erasing it again.
+        let rom: [u8; 10] = [0x61, 0x02, 0x62, 0x03, 0xA0, 0x00, 0xD1, 0x25, 0xD1, 0x25];
+        let mut quirks: Quirks = Quirks::new();
+        quirks.display_wait = false;
+        let mut processor: Processor = processor(&rom, quirks);
+        run(&mut processor, 4);
+        assert!(lit(&processor, 2, 3));
+        assert_eq!(processor.state().registers[0xF], 0);
+        run(&mut processor, 1);
+        assert!(!lit(&processor, 2, 3));
+        assert_eq!(processor.state().registers[0xF], 1);
+    }
+
+    #[test]
+    fn jump_offsets_by_v0_or_by_vx_with_the_quirk() {
+        // V0 = 0x10, V3 = 0x20, then B300.
+        let rom: [u8; 6] = [0x60, 0x10, 0x63, 0x20, 0xB3, 0x00];
+        let mut quirks: Quirks = Quirks::new();
+        quirks.jump_uses_vx = false;
+        let mut plain: Processor = processor(&rom, quirks);
+        run(&mut plain, 3);
+        assert_eq!(plain.program_counter(), 0x310);
+
+        quirks.jump_uses_vx = true;
+        let mut quirky: Processor = processor(&rom, quirks);
+        run(&mut quirky, 3);
+        assert_eq!(quirky.program_counter(), 0x320);
+    }
+
+    // VX and VF after `V1 = a, V2 = b`, then 8 1 2 `operation`, with VX shifted in place.
+    fn alu(a: u8, b: u8, operation: u8) -> (u8, u8) {
+        let mut quirks: Quirks = Quirks::new();
+        quirks.shift_uses_vy = false;
+        let mut processor: Processor =
+            processor(&[0x61, a, 0x62, b, 0x81, 0x20 | operation], quirks);
+        run(&mut processor, 3);
+        let registers: [u8; 16] = processor.state().registers;
+        return (registers[1], registers[0xF]);
+    }
+
+    #[test]
+    fn arithmetic_wraps_and_flags_carry_and_borrow() {
+        assert_eq!(alu(0xF0, 0x20, 0x4), (0x10, 1));
+        assert_eq!(alu(0x10, 0x20, 0x4), (0x30, 0));
+        // 8XY5 and 8XY7 set VF when there is no borrow, equal operands included.
+        assert_eq!(alu(0x10, 0x20, 0x5), (0xF0, 0));
+        assert_eq!(alu(0x20, 0x20, 0x5), (0x00, 1));
+        assert_eq!(alu(0x20, 0x10, 0x7), (0xF0, 0));
+        assert_eq!(alu(0x10, 0x20, 0x7), (0x10, 1));
+    }
+
+    #[test]
+    fn shifts_put_the_bit_shifted_out_in_vf() {
+        assert_eq!(alu(0x81, 0, 0x6), (0x40, 1));
+        assert_eq!(alu(0x80, 0, 0x6), (0x40, 0));
+        assert_eq!(alu(0x80, 0, 0xE), (0x00, 1));
+        assert_eq!(alu(0x41, 0, 0xE), (0x82, 0));
+    }
+
+    #[test]
+    fn flag_wins_when_vf_is_the_destination() {
+        // VF = 0xFF, V1 = 2, then ADD VF, V1.
+        let mut processor: Processor =
+            processor(&[0x6F, 0xFF, 0x61, 0x02, 0x8F, 0x14], Quirks::new());
+        run(&mut processor, 3);
+        assert_eq!(processor.state().registers[0xF], 1);
+    }
+
+    fn change_key(processor: &mut Processor, key: Key, position: Position) {
+        processor.keypad_mut().queue(KeyEvent { key, position, at: Instant::now() });
+        processor.apply_key_events();
+    }
+
+    fn waiting_on_fx0a(key_wait: KeyWaitMode) -> Processor {
+        let mut quirks: Quirks = Quirks::new();
+        quirks.key_wait = key_wait;
+        // LD V3, K then V4 = 1.
+        let mut processor: Processor = processor(&[0xF3, 0x0A, 0x64, 0x01], quirks);
+        run(&mut processor, 1);
+        return processor;
+    }
+
+    #[test]
+    fn fx0a_holds_the_cpu_until_a_key() {
+        let mut processor: Processor = waiting_on_fx0a(KeyWaitMode::Press);
+        for _ in 0..3 {
+            assert_eq!(processor.step(), Ok(StepOutcome::WaitingForKey));
+            processor.apply_key_events();
         }
+        assert_eq!(processor.program_counter(), 0x202);
+        assert_eq!(processor.state().waiting_for_key, Some(3));
+        assert_eq!(processor.state().registers[4], 0);
+    }
+
+    #[test]
+    fn fx0a_resolves_on_a_press() {
+        let mut processor: Processor = waiting_on_fx0a(KeyWaitMode::Press);
+        change_key(&mut processor, Key::SEVEN, Position::DOWN);
+        assert!(!processor.is_waiting());
+        assert_eq!(processor.state().registers[3], 7);
+        run(&mut processor, 1);
+        assert_eq!(processor.state().registers[4], 1);
+    }
+
+    #[test]
+    fn fx0a_resolves_on_a_release_with_the_quirk() {
+        let mut processor: Processor = waiting_on_fx0a(KeyWaitMode::Release);
+        change_key(&mut processor, Key::C, Position::DOWN);
+        assert!(processor.is_waiting());
+        change_key(&mut processor, Key::C, Position::UP);
+        assert!(!processor.is_waiting());
+        assert_eq!(processor.state().registers[3], 0xC);
+
+        // A tap within one frame counts too.
+        let mut processor: Processor = waiting_on_fx0a(KeyWaitMode::Release);
+        let now: Instant = Instant::now();
+        processor.keypad_mut().queue(KeyEvent { key: Key::TWO, position: Position::DOWN, at: now });
+        processor.keypad_mut().queue(KeyEvent { key: Key::TWO, position: Position::UP, at: now });
+        processor.apply_key_events();
+        assert_eq!((processor.is_waiting(), processor.state().registers[3]), (false, 2));
+    }
+
+    #[test]
+    fn fx0a_ignores_a_key_already_held() {
+        let mut quirks: Quirks = Quirks::new();
+        quirks.key_wait = KeyWaitMode::Press;
+        let mut processor: Processor = processor(&[0xF3, 0x0A], quirks);
+        change_key(&mut processor, Key::ONE, Position::DOWN);
+        run(&mut processor, 1);
+        processor.apply_key_events();
+        assert!(processor.is_waiting());
+        change_key(&mut processor, Key::FIVE, Position::DOWN);
+        assert_eq!((processor.is_waiting(), processor.state().registers[3]), (false, 5));
+    }
+
+    // Run until something goes wrong, there is a limit in case nothing does.
+    fn fault(rom: &[u8]) -> EmulatorError {
+        let mut quirks: Quirks = Quirks::new();
+        quirks.display_wait = false;
+        let mut processor: Processor = processor(rom, quirks);
+        for _ in 0..100 {
+            if let Err(error) = processor.step() {
+                return error;
+            }
+        }
+        panic!("no fault in 100 instructions");
+    }
+
+    #[test]
+    fn unknown_and_machine_code_opcodes_are_invalid() {
+        assert_eq!(fault(&[0x00, 0xE0, 0xE0, 0xFF]),
+            EmulatorError::InvalidOpcode { pc: 0x202, opcode: 0xE0FF });
+        assert_eq!(fault(&[0x01, 0x23]),
+            EmulatorError::InvalidOpcode { pc: 0x200, opcode: 0x0123 });
+    }
+
+    #[test]
+    fn calls_and_returns_stay_within_the_stack() {
+        // CALL 0x200 forever, until the 17th does not fit.
+        assert_eq!(fault(&[0x22, 0x00]), EmulatorError::StackOverflow { pc: 0x200 });
+        assert_eq!(fault(&[0x00, 0xE0, 0x00, 0xEE]), EmulatorError::StackUnderflow { pc: 0x202 });
+    }
+
+    #[test]
+    fn accesses_past_the_end_of_memory_fail() {
+        // I = 0xFFE, then FX55, FX65, FX33 and DXYN running off the end.
+        let past_end: EmulatorError = EmulatorError::MemoryOutOfBounds { addr: 0x1000 };
+        assert_eq!(fault(&[0xAF, 0xFE, 0xF2, 0x55]), past_end);
+        assert_eq!(fault(&[0xAF, 0xFE, 0xF2, 0x65]), past_end);
+        assert_eq!(fault(&[0xAF, 0xFE, 0xF0, 0x33]), past_end);
+        assert_eq!(fault(&[0xAF, 0xFE, 0xD0, 0x03]), past_end);
+        // Up to the last byte is fine.
+        let mut processor: Processor = processor(&[0xAF, 0xFE, 0xF1, 0x55], Quirks::new());
+        run(&mut processor, 2);
+    }
+
+    #[test]
+    fn the_program_counter_cannot_leave_memory() {
+        // JP 0xFFF, which leaves half an instruction to fetch.
+        assert_eq!(fault(&[0x1F, 0xFF]), EmulatorError::PcOutOfRange { pc: 0xFFF });
+    }
+
+    #[test]
+    fn roms_must_fit_between_0x200_and_the_end() {
+        let mut processor: Processor =
+            Processor::new(Memory::new(), Graphics::new(), Keypad::new(), Sound::new());
+        assert_eq!(processor.init(&[0; 0xE00]), Ok(()));
+        assert_eq!(processor.init(&[0; 0xE01]), Err(EmulatorError::RomTooLarge { size: 0xE01 }));
     }
 }
@@ -0,0 +1,153 @@
+use super::keypad::KeyWaitMode;
+use super::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+// What FX55/FX65 leave in I after storing or loading V0..VX.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexIncrement {
+    Unchanged,
+    // I += X, the off-by-one in CHIP-48.
+    ByX,
+    // I += X + 1, pointing just past the last byte touched.
+    ByXPlusOne,
+}
+
+// The behaviours CHIP-8 interpreters historically disagreed on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY into VX, rather than shifting VX in place.
+    pub shift_uses_vy: bool,
+    pub index_increment: IndexIncrement,
+    // BNNN jumps to XNN + VX, rather than NNN + V0.
+    pub jump_uses_vx: bool,
+    // 8XY1/8XY2/8XY3 reset VF to zero.
+    pub logic_resets_vf: bool,
+    // Sprites are cut off at the edge of the screen, rather than wrapping around.
+    pub clip_sprites: bool,
+    // DXYN waits for the next 60 Hz frame, so only one sprite is drawn per frame.
+    pub display_wait: bool,
+    pub key_wait: KeyWaitMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuirkProfile {
+    CosmacVip,
+    Chip48,
+    SuperChipModern,
+    SuperChipLegacy,
+    XoChip,
+}
+
+impl QuirkProfile {
+    pub const ALL: [QuirkProfile; 5] = [
+        QuirkProfile::CosmacVip,
+        QuirkProfile::Chip48,
+        QuirkProfile::SuperChipModern,
+        QuirkProfile::SuperChipLegacy,
+        QuirkProfile::XoChip,
+    ];
+
+    pub fn name(self) -> &'static str {
+        return match self {
+            QuirkProfile::CosmacVip => "cosmac-vip",
+            QuirkProfile::Chip48 => "chip-48",
+            QuirkProfile::SuperChipModern => "schip-modern",
+            QuirkProfile::SuperChipLegacy => "schip-legacy",
+            QuirkProfile::XoChip => "xo-chip",
+        };
+    }
+    pub fn from_name(name: &str) -> Option<QuirkProfile> {
+        return QuirkProfile::ALL.into_iter().find(|profile| profile.name() == name);
+    }
+    pub fn quirks(self) -> Quirks {
+        return match self {
+            QuirkProfile::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                index_increment: IndexIncrement::ByXPlusOne,
+                jump_uses_vx: false,
+                logic_resets_vf: true,
+                clip_sprites: true,
+                display_wait: true,
+                key_wait: KeyWaitMode::Release,
+            },
+            QuirkProfile::Chip48 => Quirks {
+                shift_uses_vy: false,
+                index_increment: IndexIncrement::ByX,
+                jump_uses_vx: true,
+                logic_resets_vf: false,
+                clip_sprites: true,
+                display_wait: false,
+                key_wait: KeyWaitMode::Release,
+            },
+            QuirkProfile::SuperChipModern => Quirks {
+                shift_uses_vy: false,
+                index_increment: IndexIncrement::Unchanged,
+                jump_uses_vx: true,
+                logic_resets_vf: false,
+                clip_sprites: true,
+                display_wait: false,
+                key_wait: KeyWaitMode::Press,
+            },
+            QuirkProfile::SuperChipLegacy => Quirks {
+                shift_uses_vy: false,
+                index_increment: IndexIncrement::Unchanged,
+                jump_uses_vx: true,
+                logic_resets_vf: false,
+                clip_sprites: true,
+                display_wait: true,
+                key_wait: KeyWaitMode::Release,
+            },
+            QuirkProfile::XoChip => Quirks {
+                shift_uses_vy: true,
+                index_increment: IndexIncrement::ByXPlusOne,
+                jump_uses_vx: false,
+                logic_resets_vf: false,
+                clip_sprites: false,
+                display_wait: false,
+                key_wait: KeyWaitMode::Press,
+            },
+        };
+    }
+}
+
+impl Quirks {
+    pub fn new() -> Quirks {
+        return QuirkProfile::CosmacVip.quirks();
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        return Quirks::new();
+    }
+}
+
+impl SaveState for Quirks {
+    fn save(&self, writer: &mut StateWriter) -> () {
+        writer.put_bool(self.shift_uses_vy);
+        writer.put_u8(match self.index_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => 1,
+            IndexIncrement::ByXPlusOne => 2,
+        });
+        writer.put_bool(self.jump_uses_vx);
+        writer.put_bool(self.logic_resets_vf);
+        writer.put_bool(self.clip_sprites);
+        writer.put_bool(self.display_wait);
+        writer.put_bool(self.key_wait == KeyWaitMode::Release);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.shift_uses_vy = reader.get_bool()?;
+        self.index_increment = match reader.get_u8()? {
+            0 => IndexIncrement::Unchanged,
+            1 => IndexIncrement::ByX,
+            2 => IndexIncrement::ByXPlusOne,
+            _ => return Err(SaveStateError::Corrupt),
+        };
+        self.jump_uses_vx = reader.get_bool()?;
+        self.logic_resets_vf = reader.get_bool()?;
+        self.clip_sprites = reader.get_bool()?;
+        self.display_wait = reader.get_bool()?;
+        self.key_wait = if reader.get_bool()? {KeyWaitMode::Release} else {KeyWaitMode::Press};
+        return Ok(());
+    }
+}
@@ -0,0 +1,191 @@
+// Where CXNN gets its random bytes from. The machine owns one, and its whole state fits in a
+// u64 so that it can go into save states and movies.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+    fn state(&self) -> u64;
+    fn set_state(&mut self, state: u64);
+    // Called at 60 Hz, for generators that depend on timing.
+    fn tick_frame(&mut self) {}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RandomMode {
+    Xorshift,
+    // Not the COSMAC VIP routine, which reads its bytes out of the VIP interpreter's own code,
+    // and that code is not part of this machine.
+    Table,
+}
+
+impl RandomMode {
+    pub fn name(self) -> &'static str {
+        return match self {
+            RandomMode::Xorshift => "xorshift",
+            RandomMode::Table => "table",
+        };
+    }
+    pub fn from_name(name: &str) -> Option<RandomMode> {
+        return [RandomMode::Xorshift, RandomMode::Table].into_iter()
+            .find(|mode| mode.name() == name);
+    }
+    // How save states and movies write the mode down.
+    pub fn id(self) -> u8 {
+        return match self {
+            RandomMode::Xorshift => 0,
+            RandomMode::Table => 1,
+        };
+    }
+    pub fn from_id(id: u8) -> Option<RandomMode> {
+        return [RandomMode::Xorshift, RandomMode::Table].into_iter().find(|mode| mode.id() == id);
+    }
+    pub fn source(self, seed: u64) -> Box<dyn RandomSource> {
+        return match self {
+            RandomMode::Xorshift => Box::new(Xorshift::new(seed)),
+            RandomMode::Table => Box::new(TableRandom::new(seed)),
+        };
+    }
+}
+
+pub fn entropy_seed() -> u64 {
+    return rand::random::<u64>();
+}
+
+// xorshift64*, the deterministic default.
+pub struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Xorshift {
+        let mut xorshift: Xorshift = Xorshift { state: 0 };
+        xorshift.set_state(seed);
+        return xorshift;
+    }
+}
+
+impl RandomSource for Xorshift {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8;
+    }
+    fn state(&self) -> u64 {
+        return self.state;
+    }
+    fn set_state(&mut self, state: u64) {
+        // An all-zero state would only ever produce zeros.
+        self.state = if state == 0 {0x9E3779B97F4A7C15} else {state};
+    }
+}
+
+// Bytes for `TableRandom` to walk, generated here rather than taken from any interpreter.
+const TABLE: [u8; 256] = {
+    let mut table: [u8; 256] = [0; 256];
+    let mut value: u32 = 0x2F;
+    let mut i: usize = 0;
+    while i < 256 {
+        value = value.wrapping_mul(1103515245).wrapping_add(12345) & 0x7FFFFFFF;
+        table[i] = (value >> 16) as u8;
+        i += 1;
+    }
+    table
+};
+
+// A 16-bit register whose low byte also counts frames, the high byte accumulating the table
+// bytes the low byte points at. Cheap and visibly not very random, in the spirit of early
+// interpreters that read their bytes from their own code, but not a copy of any of them.
+pub struct TableRandom {
+    register: u16,
+}
+
+impl TableRandom {
+    pub fn new(seed: u64) -> TableRandom {
+        TableRandom { register: seed as u16 }
+    }
+}
+
+impl RandomSource for TableRandom {
+    fn next_byte(&mut self) -> u8 {
+        let low: u8 = (self.register as u8).wrapping_add(1);
+        let high: u8 = ((self.register >> 8) as u8).wrapping_add(TABLE[low as usize]);
+        self.register = u16::from_be_bytes([high, low]);
+        return high;
+    }
+    fn state(&self) -> u64 {
+        return self.register as u64;
+    }
+    fn set_state(&mut self, state: u64) {
+        self.register = state as u16;
+    }
+    fn tick_frame(&mut self) {
+        let [high, low] = self.register.to_be_bytes();
+        self.register = u16::from_be_bytes([high, low.wrapping_add(1)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::api::StepOutcome;
+    use super::super::builder::ChipEightBuilder;
+    use super::super::chip_eight::ChipEight;
+    use super::super::scheduler::Pacing;
+
+    const MODES: [RandomMode; 2] = [RandomMode::Xorshift, RandomMode::Table];
+
+    fn bytes(source: &mut dyn RandomSource, count: usize) -> Vec<u8> {
+        return (0..count).map(|_| source.next_byte()).collect();
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_bytes() {
+        for mode in MODES {
+            let first: Vec<u8> = bytes(mode.source(1234).as_mut(), 64);
+            assert_eq!(bytes(mode.source(1234).as_mut(), 64), first, "{}", mode.name());
+            assert_ne!(bytes(mode.source(1235).as_mut(), 64), first, "{}", mode.name());
+        }
+    }
+
+    #[test]
+    fn carries_on_from_a_restored_state() {
+        for mode in MODES {
+            let mut source: Box<dyn RandomSource> = mode.source(99);
+            bytes(source.as_mut(), 10);
+            source.tick_frame();
+            let mut restored: Box<dyn RandomSource> = mode.source(0);
+            restored.set_state(source.state());
+            assert_eq!(bytes(restored.as_mut(), 32), bytes(source.as_mut(), 32), "{}", mode.name());
+        }
+    }
+
+    // CXNN into V0 over and over, returning what each one gave.
+    fn draws(chip_eight: &mut ChipEight, count: usize) -> Vec<u8> {
+        let mut values: Vec<u8> = Vec::new();
+        while values.len() < count {
+            if let StepOutcome::Executed { .. } = chip_eight.step().unwrap() {
+                values.push(chip_eight.state().registers[0]);
+            }
+        }
+        return values;
+    }
+
+    #[test]
+    fn survives_a_save_state_round_trip() {
+        for mode in MODES {
+            // C0FF, then jump back to it.
+            let rom: [u8; 4] = [0xC0, 0xFF, 0x12, 0x00];
+            let mut chip_eight: ChipEight = ChipEightBuilder::new()
+                .pacing(Pacing::Turbo).random_mode(mode).seed(7).build();
+            chip_eight.load_rom(&rom).unwrap();
+            draws(&mut chip_eight, 20);
+            let state: Vec<u8> = chip_eight.save_state();
+            let expected: Vec<u8> = draws(&mut chip_eight, 40);
+
+            let mut restored: ChipEight = ChipEightBuilder::new()
+                .pacing(Pacing::Turbo).random_mode(mode).seed(1).build();
+            restored.load_rom(&rom).unwrap();
+            restored.load_state(&state).unwrap();
+            assert_eq!(draws(&mut restored, 40), expected, "{}", mode.name());
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::VecDeque;
+
+use super::chip_eight::ChipEight;
+use super::save_state::SaveStateError;
+
+// A keyframe every second, the frames in between are stored as deltas against it.
+pub const KEYFRAME_INTERVAL: usize = 60;
+// Ten minutes or so of typical play.
+pub const DEFAULT_BUDGET: usize = 4 * 1024 * 1024;
+
+// Anything that can be captured and put back whole, frame by frame.
+pub trait Snapshot {
+    fn snapshot(&self) -> Vec<u8>;
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), SaveStateError>;
+}
+
+impl Snapshot for ChipEight {
+    fn snapshot(&self) -> Vec<u8> {
+        return self.save_state();
+    }
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), SaveStateError> {
+        return self.load_state(snapshot);
+    }
+}
+
+// A keyframe and the frames recorded after it, the oldest group is dropped as a whole.
+struct Group {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Group {
+    fn size(&self) -> usize {
+        return self.keyframe.len() + self.deltas.iter().map(Vec::len).sum::<usize>();
+    }
+}
+
+// Ring buffer of past frames bounded by the number of bytes it holds.
+pub struct Rewind {
+    groups: VecDeque<Group>,
+    budget: usize,
+    size: usize,
+}
+
+impl Rewind {
+    pub fn new(budget: usize) -> Rewind {
+        Rewind { groups: VecDeque::new(), budget, size: 0 }
+    }
+    // Call before running each frame, so stepping back lands on the start of that frame.
+    pub fn record<M: Snapshot>(&mut self, machine: &M) {
+        let snapshot: Vec<u8> = machine.snapshot();
+        let delta: Option<Vec<u8>> = match self.groups.back() {
+            Some(group) if group.deltas.len() + 1 < KEYFRAME_INTERVAL
+                && group.keyframe.len() == snapshot.len() =>
+                Some(encode_delta(&group.keyframe, &snapshot)),
+            _ => None,
+        };
+        match delta {
+            Some(delta) => {
+                self.size += delta.len();
+                self.groups.back_mut().unwrap().deltas.push(delta);
+            }
+            None => {
+                self.size += snapshot.len();
+                self.groups.push_back(Group { keyframe: snapshot, deltas: Vec::new() });
+            }
+        }
+        // Always keep the group being recorded into, however small the budget.
+        while self.size > self.budget && self.groups.len() > 1 {
+            let oldest: Group = self.groups.pop_front().unwrap();
+            self.size -= oldest.size();
+        }
+    }
+    // Put the machine back to the most recently recorded frame and forget it. Returns false
+    // once there is no history left.
+    pub fn step_back<M: Snapshot>(&mut self, machine: &mut M) -> Result<bool, SaveStateError> {
+        let Some(group) = self.groups.back_mut() else {
+            return Ok(false);
+        };
+        let snapshot: Vec<u8> = match group.deltas.pop() {
+            Some(delta) => {
+                self.size -= delta.len();
+                decode_delta(&group.keyframe, &delta)?
+            }
+            None => {
+                let group: Group = self.groups.pop_back().unwrap();
+                self.size -= group.keyframe.len();
+                group.keyframe
+            }
+        };
+        machine.restore(&snapshot)?;
+        return Ok(true);
+    }
+    // How many frames can be stepped back.
+    pub fn frames(&self) -> usize {
+        return self.groups.iter().map(|group| group.deltas.len() + 1).sum();
+    }
+    pub fn size(&self) -> usize {
+        return self.size;
+    }
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.size = 0;
+    }
+}
+
+// The XOR of two snapshots is mostly zeros, so it is stored as runs:
+// (zeros to skip, literal length, literal bytes) repeated, lengths as LEB128.
+fn encode_delta(keyframe: &[u8], snapshot: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = keyframe.iter().zip(snapshot).map(|(a, b)| a ^ b).collect();
+    let mut delta: Vec<u8> = Vec::new();
+    let mut i: usize = 0;
+    while i < xor.len() {
+        let zeros: usize = xor[i..].iter().take_while(|byte| **byte == 0).count();
+        i += zeros;
+        if i == xor.len() { break; }
+
+        // A literal carries on through lone zeros, breaking off costs more than it saves.
+        let start: usize = i;
+        while i < xor.len() && (xor[i] != 0 || xor.get(i + 1).is_some_and(|byte| *byte != 0)) {
+            i += 1;
+        }
+        put_length(&mut delta, zeros);
+        put_length(&mut delta, i - start);
+        delta.extend_from_slice(&xor[start..i]);
+    }
+    return delta;
+}
+
+fn decode_delta(keyframe: &[u8], delta: &[u8]) -> Result<Vec<u8>, SaveStateError> {
+    let mut snapshot: Vec<u8> = keyframe.to_vec();
+    let mut position: usize = 0;
+    let mut rest: &[u8] = delta;
+    while !rest.is_empty() {
+        position += get_length(&mut rest)?;
+        let length: usize = get_length(&mut rest)?;
+        if rest.len() < length || position + length > snapshot.len() {
+            return Err(SaveStateError::Corrupt);
+        }
+        for (byte, xor) in snapshot[position..position + length].iter_mut().zip(&rest[..length]) {
+            *byte ^= xor;
+        }
+        position += length;
+        rest = &rest[length..];
+    }
+    return Ok(snapshot);
+}
+
+fn put_length(bytes: &mut Vec<u8>, mut length: usize) {
+    while length >= 0x80 {
+        bytes.push((length as u8 & 0x7F) | 0x80);
+        length >>= 7;
+    }
+    bytes.push(length as u8);
+}
+
+fn get_length(bytes: &mut &[u8]) -> Result<usize, SaveStateError> {
+    let mut length: usize = 0;
+    let mut shift: u32 = 0;
+    loop {
+        let Some((byte, rest)) = bytes.split_first() else {
+            return Err(SaveStateError::Truncated);
+        };
+        *bytes = rest;
+        if shift >= usize::BITS {
+            return Err(SaveStateError::Corrupt);
+        }
+        length |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 { return Ok(length); }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(keyframe: &[u8], snapshot: &[u8]) -> Vec<u8> {
+        let delta: Vec<u8> = encode_delta(keyframe, snapshot);
+        assert_eq!(decode_delta(keyframe, &delta).unwrap(), snapshot);
+        return delta;
+    }
+
+    #[test]
+    fn identical_snapshots_need_no_delta() {
+        assert_eq!(round_trip(&[1, 2, 3], &[1, 2, 3]), []);
+        assert_eq!(round_trip(&[], &[]), []);
+    }
+
+    #[test]
+    fn deltas_skip_zeros_and_carry_on_through_lone_ones() {
+        // Changes at 1, 3 and 4: one run through the lone zero at 2.
+        assert_eq!(round_trip(&[0; 6], &[0, 5, 0, 6, 7, 0]), [1, 4, 5, 0, 6, 7]);
+        // Two zeros in a row end the run.
+        assert_eq!(round_trip(&[0; 6], &[9, 0, 0, 8, 0, 0]), [0, 1, 9, 2, 1, 8]);
+        // Changes in the last byte, and in every byte.
+        round_trip(&[1, 2, 3, 4], &[1, 2, 3, 5]);
+        round_trip(&[1, 2, 3, 4], &[4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn long_runs_take_several_length_bytes() {
+        let keyframe: Vec<u8> = vec![0; 70_000];
+        let mut snapshot: Vec<u8> = keyframe.clone();
+        snapshot[200] = 1;
+        snapshot[69_999] = 2;
+        assert_eq!(round_trip(&keyframe, &snapshot), [0xC8, 0x01, 1, 1, 0xA6, 0xA1, 0x04, 1, 2]);
+        let literal: Vec<u8> = (0..300).map(|i| (i % 255) as u8 + 1).collect();
+        round_trip(&vec![0; 300], &literal);
+    }
+
+    #[test]
+    fn broken_deltas_are_errors() {
+        let keyframe: [u8; 4] = [0; 4];
+        // Length byte with its continuation bit set and nothing after it.
+        assert_eq!(decode_delta(&keyframe, &[0x80]), Err(SaveStateError::Truncated));
+        // Fewer literal bytes than promised.
+        assert_eq!(decode_delta(&keyframe, &[0, 3, 1]), Err(SaveStateError::Corrupt));
+        // A run past the end of the snapshot.
+        assert_eq!(decode_delta(&keyframe, &[3, 2, 1, 1]), Err(SaveStateError::Corrupt));
+        // A length with more bits than a usize holds.
+        assert_eq!(decode_delta(&keyframe, &[0x80; 11]), Err(SaveStateError::Corrupt));
+    }
+
+    struct Machine(Vec<u8>);
+
+    impl Snapshot for Machine {
+        fn snapshot(&self) -> Vec<u8> {
+            return self.0.clone();
+        }
+        fn restore(&mut self, snapshot: &[u8]) -> Result<(), SaveStateError> {
+            self.0 = snapshot.to_vec();
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn steps_back_through_keyframes_and_deltas() {
+        let mut rewind: Rewind = Rewind::new(DEFAULT_BUDGET);
+        let mut machine: Machine = Machine(vec![0; 64]);
+        for frame in 0..150usize {
+            machine.0[frame % 64] = frame as u8;
+            rewind.record(&machine);
+        }
+        assert_eq!(rewind.frames(), 150);
+        assert_eq!(rewind.groups.len(), 3);
+        for frame in (0..150usize).rev() {
+            assert!(rewind.step_back(&mut machine).unwrap());
+            assert_eq!(machine.0[frame % 64], frame as u8);
+        }
+        assert!(!rewind.step_back(&mut machine).unwrap());
+        assert_eq!(rewind.size(), 0);
+    }
+
+    #[test]
+    fn drops_the_oldest_second_over_budget() {
+        let mut rewind: Rewind = Rewind::new(200);
+        let mut machine: Machine = Machine(vec![0; 100]);
+        for frame in 0..KEYFRAME_INTERVAL * 3 {
+            machine.0[0] = frame as u8;
+            rewind.record(&machine);
+        }
+        // The group being recorded into stays even though it is over budget on its own.
+        assert_eq!(rewind.groups.len(), 1);
+        assert_eq!(rewind.size(), rewind.groups[0].size());
+        assert_eq!(rewind.frames(), KEYFRAME_INTERVAL);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::disassembler::{self, Disassembly};
+use super::instruction::Instruction;
+use super::memory::{PROGRAM_END, PROGRAM_START};
+use crate::common::hash_utils;
+
+// Which machine a ROM was written for, guessed from the opcodes its code uses. Each is a
+// superset of the one before.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        })
+    }
+}
+
+pub struct RomInfo {
+    pub size: usize,
+    pub sha1: [u8; 20],
+    pub platform: Platform,
+    // Instructions reachable from the entry point, counted by opcode pattern.
+    pub opcodes: BTreeMap<&'static str, usize>,
+    // Opcodes the code runs into that this interpreter does not know, by address.
+    pub unknown: BTreeMap<u16, u16>,
+}
+
+pub fn analyze(rom: &[u8]) -> RomInfo {
+    let disassembly: Disassembly = disassembler::recursive(rom);
+    let mut opcodes: BTreeMap<&'static str, usize> = BTreeMap::new();
+    let mut code: BTreeSet<u16> = BTreeSet::new();
+    let mut successors: BTreeSet<u16> = BTreeSet::new();
+    let mut platform: Platform = Platform::Chip8;
+    for line in disassembly.lines.iter().chain(&disassembly.overlapping) {
+        let Some(instruction) = line.instruction else { continue; };
+        *opcodes.entry(instruction.pattern()).or_insert(0) += 1;
+        code.insert(line.address);
+        match instruction {
+            Instruction::Jp(target) => { successors.insert(target); }
+            Instruction::Ret | Instruction::JpV0(_) => {}
+            Instruction::Call(target) => {
+                successors.insert(target);
+                successors.insert(line.address + 2);
+            }
+            _ => { successors.insert(line.address + 2); }
+        }
+        platform = platform.max(detect(instruction));
+    }
+
+    // Code that runs into an opcode nobody decodes is the best hint of a later platform.
+    let opcode_at = |address: u16| -> Option<u16> {
+        let offset: usize = (address as usize).checked_sub(PROGRAM_START)?;
+        return Some(u16::from_be_bytes([*rom.get(offset)?, *rom.get(offset + 1)?]));
+    };
+    let mut unknown: BTreeMap<u16, u16> = BTreeMap::new();
+    for address in successors.difference(&code) {
+        if let Some(opcode) = opcode_at(*address) {
+            unknown.insert(*address, opcode);
+            platform = platform.max(detect_unknown(opcode));
+        }
+    }
+    // Only XO-CHIP has the memory for ROMs that do not fit below 0x1000.
+    if rom.len() > PROGRAM_END + 1 - PROGRAM_START {
+        platform = Platform::XoChip;
+    }
+    return RomInfo { size: rom.len(), sha1: hash_utils::sha1(rom), platform, opcodes, unknown };
+}
+
+// SUPER-CHIP and XO-CHIP opcodes that decode as something else here.
+fn detect(instruction: Instruction) -> Platform {
+    return match instruction {
+        // Scroll down, scroll right/left, exit, low/high resolution.
+        Instruction::Sys(0x0C0..=0x0CF | 0x0FB..=0x0FF) => Platform::SuperChip,
+        // Scroll up.
+        Instruction::Sys(0x0D0..=0x0DF) => Platform::XoChip,
+        // 16x16 sprites.
+        Instruction::Drw { n: 0, .. } => Platform::SuperChip,
+        _ => Platform::Chip8,
+    };
+}
+
+fn detect_unknown(opcode: u16) -> Platform {
+    return match (opcode >> 12, opcode & 0x00FF) {
+        // Save and load a register range, long I, audio pattern, plane select, pitch.
+        (0x5, _) if matches!(opcode & 0xF, 0x2 | 0x3) => Platform::XoChip,
+        (0xF, 0x00 | 0x02) if opcode & 0x0F00 == 0 => Platform::XoChip,
+        (0xF, 0x01 | 0x3A) => Platform::XoChip,
+        // Large font, save and load flags.
+        (0xF, 0x30 | 0x75 | 0x85) => Platform::SuperChip,
+        _ => Platform::Chip8,
+    };
+}
+
+impl RomInfo {
+    pub fn render(&self) -> String {
+        let mut text: String = format!(
+            "size:     {} bytes\nsha1:     {}\nplatform: {}\n",
+            self.size, hash_utils::to_hex(&self.sha1), self.platform);
+        let total: usize = self.opcodes.values().sum();
+        text.push_str(&format!("opcodes:  {} reachable instructions\n", total));
+        let mut counts: Vec<(&&str, &usize)> = self.opcodes.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (pattern, count) in counts {
+            text.push_str(&format!("  {}  {:>5}  {:>5.1}%\n",
+                pattern, count, *count as f64 * 100.0 / total as f64));
+        }
+        for (address, opcode) in &self.unknown {
+            text.push_str(&format!("unknown opcode {:04X} at 0x{:03X}\n", opcode, address));
+        }
+        return text;
+    }
+}
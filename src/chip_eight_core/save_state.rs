@@ -0,0 +1,151 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::common::hash_utils;
+
+// File layout: magic, format version, SHA-1 of the ROM, then each component in turn.
+const MAGIC: &[u8; 4] = b"C8SS";
+pub const VERSION: u8 = 1;
+pub const SLOTS: u8 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveStateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    // The state was saved while running a different ROM.
+    RomMismatch,
+    // The state's random number generator is not the one the machine runs, so its state would
+    // mean nothing to it.
+    RandomModeMismatch,
+    Truncated,
+    // A value that no machine could have produced, e.g. a stack pointer past the stack.
+    Corrupt,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) =>
+                write!(f, "unsupported save state version {}", version),
+            SaveStateError::RomMismatch => write!(f, "save state belongs to a different rom"),
+            SaveStateError::RandomModeMismatch =>
+                write!(f, "save state uses a different random number generator"),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Corrupt => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+// Implemented by every component that makes up the machine.
+pub trait SaveState {
+    fn save(&self, writer: &mut StateWriter) -> ();
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new(rom_sha1: &[u8; 20]) -> StateWriter {
+        let mut writer: StateWriter = StateWriter { bytes: Vec::new() };
+        writer.put_bytes(MAGIC);
+        writer.put_u8(VERSION);
+        writer.put_bytes(rom_sha1);
+        return writer;
+    }
+    // No header, for formats that embed components or save states of their own.
+    pub fn empty() -> StateWriter {
+        return StateWriter { bytes: Vec::new() };
+    }
+    pub fn put_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    pub fn put_bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+    pub fn put_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+    pub fn put_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+    pub fn put_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+    pub fn finish(self) -> Vec<u8> {
+        return self.bytes;
+    }
+}
+
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    // Check the header, leaving the reader at the first component.
+    pub fn new(bytes: &'a [u8], rom_sha1: &[u8; 20]) -> Result<StateReader<'a>, SaveStateError> {
+        let mut reader: StateReader = StateReader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version: u8 = reader.get_u8()?;
+        if version != VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        if reader.take(20)? != rom_sha1 {
+            return Err(SaveStateError::RomMismatch);
+        }
+        return Ok(reader);
+    }
+    pub fn raw(bytes: &'a [u8]) -> StateReader<'a> {
+        return StateReader { bytes };
+    }
+    pub fn take(&mut self, count: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.bytes.len() < count {
+            return Err(SaveStateError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        return Ok(taken);
+    }
+    pub fn get_u8(&mut self) -> Result<u8, SaveStateError> {
+        return Ok(self.take(1)?[0]);
+    }
+    pub fn get_bool(&mut self) -> Result<bool, SaveStateError> {
+        return match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Corrupt),
+        };
+    }
+    pub fn get_u16(&mut self) -> Result<u16, SaveStateError> {
+        let bytes: &[u8] = self.take(2)?;
+        return Ok(u16::from_be_bytes([bytes[0], bytes[1]]));
+    }
+    pub fn get_u32(&mut self) -> Result<u32, SaveStateError> {
+        let mut bytes: [u8; 4] = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        return Ok(u32::from_be_bytes(bytes));
+    }
+    pub fn get_u64(&mut self) -> Result<u64, SaveStateError> {
+        let mut bytes: [u8; 8] = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        return Ok(u64::from_be_bytes(bytes));
+    }
+    pub fn finish(self) -> Result<(), SaveStateError> {
+        return if self.bytes.is_empty() {Ok(())} else {Err(SaveStateError::Corrupt)};
+    }
+}
+
+// Slots are kept per ROM, e.g. `<directory>/3f0a9c1e.slot2.c8s`.
+pub fn slot_path(directory: &Path, rom_sha1: &[u8; 20], slot: u8) -> PathBuf {
+    let name: String = format!("{}.slot{}.c8s", &hash_utils::to_hex(rom_sha1)[..8], slot);
+    return directory.join(name);
+}
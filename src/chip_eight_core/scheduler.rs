@@ -0,0 +1,126 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
+
+// The delay and sound timers always count down at this rate, whatever the CPU speed.
+pub const TIMER_HZ: u32 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Speed {
+    InstructionsPerSecond(u32),
+    InstructionsPerFrame(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pacing {
+    // Hold every frame to 1/60th of a second of wall-clock time.
+    RealTime,
+    // Run frames back to back as fast as the host allows.
+    Turbo,
+}
+
+// Splits emulated time into 60 Hz frames, each with a budget of CPU instructions.
+pub struct Scheduler {
+    speed: Speed,
+    pacing: Pacing,
+    // Instructions owed from previous frames when the speed is not a multiple of 60.
+    remainder: u32,
+    next_frame: Option<Instant>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            speed: Speed::InstructionsPerSecond(700),
+            pacing: Pacing::RealTime,
+            remainder: 0,
+            next_frame: None,
+        }
+    }
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.remainder = 0;
+    }
+    pub fn speed(&self) -> Speed {
+        return self.speed;
+    }
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacing = pacing;
+        self.next_frame = None;
+    }
+    // How many instructions the CPU gets in the frame that is about to start. Below 60 per
+    // second some frames get none.
+    pub fn frame_budget(&mut self) -> u32 {
+        return match self.speed {
+            Speed::InstructionsPerFrame(instructions) => instructions.max(1),
+            Speed::InstructionsPerSecond(instructions) => {
+                let owed: u32 = instructions + self.remainder;
+                self.remainder = owed % TIMER_HZ;
+                owed / TIMER_HZ
+            }
+        };
+    }
+    // Block until the current frame has taken up its share of wall-clock time.
+    pub fn wait_for_next_frame(&mut self) {
+        if self.pacing == Pacing::Turbo { return; }
+
+        let frame_length: Duration = Duration::from_secs(1) / TIMER_HZ;
+        let now: Instant = Instant::now();
+        let deadline: Instant = self.next_frame.unwrap_or(now) + frame_length;
+        if deadline > now {
+            thread::sleep(deadline - now);
+            self.next_frame = Some(deadline);
+        } else if now - deadline > frame_length * 4 {
+            // Too far behind (a breakpoint, a suspended process...), don't try to catch up.
+            self.next_frame = Some(now);
+        } else {
+            self.next_frame = Some(deadline);
+        }
+    }
+}
+
+// Only the remainder, so a restored machine gets the same instruction budgets as the one that
+// was saved. The speed and pacing are the host's to set.
+impl SaveState for Scheduler {
+    fn save(&self, writer: &mut StateWriter) -> () {
+        writer.put_u32(self.remainder);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let remainder: u32 = reader.get_u32()?;
+        if remainder >= TIMER_HZ {
+            return Err(SaveStateError::Corrupt);
+        }
+        self.remainder = remainder;
+        return Ok(());
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        return Scheduler::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budgets(speed: Speed, frames: usize) -> Vec<u32> {
+        let mut scheduler: Scheduler = Scheduler::new();
+        scheduler.set_speed(speed);
+        return (0..frames).map(|_| scheduler.frame_budget()).collect();
+    }
+
+    #[test]
+    fn spreads_the_remainder_over_frames() {
+        assert_eq!(budgets(Speed::InstructionsPerSecond(700), 3), [11, 12, 12]);
+        assert_eq!(budgets(Speed::InstructionsPerSecond(700), 60).iter().sum::<u32>(), 700);
+    }
+
+    #[test]
+    fn slow_speeds_leave_some_frames_empty() {
+        assert_eq!(budgets(Speed::InstructionsPerSecond(30), 4), [0, 1, 0, 1]);
+        assert_eq!(budgets(Speed::InstructionsPerSecond(1), 60).iter().sum::<u32>(), 1);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::chip_eight_core::api::Tick;
+use crate::chip_eight_core::save_state::{SaveState, SaveStateError, StateReader, StateWriter};
 
 pub struct Sound {
     sound_timer: u8,
@@ -13,6 +14,9 @@ impl Sound {
     pub fn set_sound_timer(&mut self, time: u8) {
         self.sound_timer = time;
     }
+    pub fn sound_timer(&self) -> u8 {
+        return self.sound_timer;
+    }
 }
 
 impl Tick for Sound {
@@ -22,3 +26,13 @@ impl Tick for Sound {
         }
     }
 }
+
+impl SaveState for Sound {
+    fn save(&self, writer: &mut StateWriter) -> () {
+        writer.put_u8(self.sound_timer);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.sound_timer = reader.get_u8()?;
+        return Ok(());
+    }
+}
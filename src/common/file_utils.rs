@@ -1,14 +1,24 @@
-use std::{fs::File, io::{Read, Error}, vec::Vec};
+use std::{fs::File, io::{Read, Error, ErrorKind}, vec::Vec};
 
 
 pub fn read_in_rom(path: &str) -> Result<Vec<u8>, Error> {
-    println!("Path to rom file: {}", path);
-    let result = File::open(path);
-    let result: Result<File, Error> = result;
-    let mut file: File = result?;
-
-    let mut test_vector: Vec<u8> = Vec::new();
-    println!("Resulting String of Garbage:\n{:?}", file.read_to_end(&mut test_vector));
-    return Ok(test_vector);
+    let mut file: File = File::open(path)?;
+
+    let mut rom: Vec<u8> = Vec::new();
+    file.read_to_end(&mut rom)?;
+    if rom.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "the file is empty"));
+    }
+    return Ok(rom);
 }
 
+// Say what went wrong reading a ROM in terms of what to do about it.
+pub fn describe_read_error(path: &str, error: &Error) -> String {
+    let reason: String = match error.kind() {
+        ErrorKind::NotFound => "no such file, check the path".to_string(),
+        ErrorKind::PermissionDenied => "permission denied".to_string(),
+        ErrorKind::IsADirectory => "that is a directory, expected a .ch8 file".to_string(),
+        _ => error.to_string(),
+    };
+    return format!("could not read ROM '{}': {}", path, reason);
+}
@@ -1,5 +1,3 @@
-use rand::prelude::*;
-
 const BITS_IN_A_HEX: u16 = 0x0004;
 
 // TODO: figure out how to add default values to variables
@@ -7,7 +5,7 @@ pub fn right_shift(opcode: u16, hex_places: u8) -> u16 {
     let mut i: u8 = 0;
     let mut left_shifted_opcode: u16 = opcode;
     while i < hex_places {
-        left_shifted_opcode = left_shifted_opcode >> BITS_IN_A_HEX;
+        left_shifted_opcode >>= BITS_IN_A_HEX;
         i += 1;
     }
     return left_shifted_opcode;
@@ -18,7 +16,10 @@ pub fn left_pad(opcode: u16, hex_places: u8) -> u16 {
     return opcode % padding;
 }
 
-pub fn random_byte() -> u8 {
-    let mut rng = rand::thread_rng();
-    return rng.gen::<u8>();
+// A number written in decimal, or in hex with a `0x` prefix.
+pub fn parse_number(text: &str) -> Option<u64> {
+    return match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
 }
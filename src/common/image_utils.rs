@@ -0,0 +1,135 @@
+use std::{fs::File, io::{BufWriter, Error, ErrorKind, Write}};
+
+use crate::chip_eight_core::api::Frame;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub on: [u8; 3],
+    pub off: [u8; 3],
+}
+
+impl Palette {
+    pub fn monochrome() -> Palette {
+        Palette { on: [0xFF, 0xFF, 0xFF], off: [0x00, 0x00, 0x00] }
+    }
+    // Parse "RRGGBB:RRGGBB" as the lit and unlit pixel colours.
+    pub fn parse(text: &str) -> Option<Palette> {
+        let (on, off) = text.split_once(':')?;
+        return Some(Palette { on: parse_colour(on)?, off: parse_colour(off)? });
+    }
+}
+
+fn parse_colour(text: &str) -> Option<[u8; 3]> {
+    let text: &str = text.trim_start_matches('#');
+    if text.len() != 6 { return None; }
+    let value: u32 = u32::from_str_radix(text, 16).ok()?;
+    return Some([(value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    // Binary portable pixmap, full colour.
+    PPM,
+    // Binary portable bitmap, black (lit) and white (unlit) only.
+    PBM,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension: String = path.rsplit('.').next()?.to_ascii_lowercase();
+        return match extension.as_str() {
+            "ppm" => Some(ImageFormat::PPM),
+            "pbm" => Some(ImageFormat::PBM),
+            _ => None,
+        };
+    }
+}
+
+pub fn write_frame(path: &str, frame: &Frame, scale: usize, palette: Palette) -> Result<(), Error> {
+    let format: ImageFormat = ImageFormat::from_path(path).ok_or_else(|| Error::new(
+        ErrorKind::InvalidInput, format!("unsupported image extension: {}", path)))?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    encode_frame(&mut writer, frame, scale, palette, format)?;
+    return writer.flush();
+}
+
+pub fn encode_frame(writer: &mut impl Write, frame: &Frame, scale: usize, palette: Palette,
+                    format: ImageFormat) -> Result<(), Error> {
+    let scale: usize = scale.max(1);
+    let (width, height) = (frame.width * scale, frame.height * scale);
+    match format {
+        ImageFormat::PPM => {
+            write!(writer, "P6\n{} {}\n255\n", width, height)?;
+            for y in 0..height {
+                for x in 0..width {
+                    let lit: bool = frame.pixel(x / scale, y / scale);
+                    writer.write_all(if lit {&palette.on} else {&palette.off})?;
+                }
+            }
+        }
+        ImageFormat::PBM => {
+            write!(writer, "P4\n{} {}\n", width, height)?;
+            // Rows are packed eight pixels to a byte, padded out to a whole byte.
+            for y in 0..height {
+                let mut row: Vec<u8> = vec![0; width.div_ceil(8)];
+                for x in 0..width {
+                    if frame.pixel(x / scale, y / scale) {
+                        row[x / 8] |= 0x80 >> (x % 8);
+                    }
+                }
+                writer.write_all(&row)?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_eight_core::api::Region;
+
+    const PIXELS: [bool; 6] = [true, false, true, false, true, false];
+
+    fn encode(scale: usize, palette: Palette, format: ImageFormat) -> Vec<u8> {
+        let frame: Frame = Frame {
+            width: 3,
+            height: 2,
+            pixels: &PIXELS,
+            dirty: Region { x: 0, y: 0, width: 3, height: 2 },
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        encode_frame(&mut bytes, &frame, scale, palette, format).unwrap();
+        return bytes;
+    }
+
+    #[test]
+    fn ppm_is_a_header_then_rgb_per_pixel() {
+        let palette: Palette = Palette::parse("010203:#040506").unwrap();
+        let (on, off) = ([1, 2, 3], [4, 5, 6]);
+        let mut expected: Vec<u8> = b"P6\n3 2\n255\n".to_vec();
+        for pixel in [on, off, on, off, on, off] {
+            expected.extend(pixel);
+        }
+        assert_eq!(encode(1, palette, ImageFormat::PPM), expected);
+        // A scale of zero is taken as one.
+        assert_eq!(encode(0, palette, ImageFormat::PPM), expected);
+    }
+
+    #[test]
+    fn pbm_packs_scaled_rows_into_whole_bytes() {
+        let mut expected: Vec<u8> = b"P4\n6 4\n".to_vec();
+        expected.extend([0b1100_1100, 0b1100_1100, 0b0011_0000, 0b0011_0000]);
+        assert_eq!(encode(2, Palette::monochrome(), ImageFormat::PBM), expected);
+    }
+
+    #[test]
+    fn parses_formats_and_palettes() {
+        assert_eq!(ImageFormat::from_path("shot.PPM"), Some(ImageFormat::PPM));
+        assert_eq!(ImageFormat::from_path("out/frame.pbm"), Some(ImageFormat::PBM));
+        assert_eq!(ImageFormat::from_path("frame.png"), None);
+        assert_eq!(Palette::parse("ffffff:000000"), Some(Palette::monochrome()));
+        assert_eq!(Palette::parse("ffffff"), None);
+        assert_eq!(Palette::parse("fffff:000000"), None);
+    }
+}
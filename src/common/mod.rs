@@ -1,2 +1,4 @@
 pub mod hex_utils;
 pub mod file_utils;
+pub mod image_utils;
+pub mod hash_utils;
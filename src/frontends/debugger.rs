@@ -0,0 +1,444 @@
+use std::io::{self, Error, Write};
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+use chip_eight::chip_eight_core::api::{Display, Frame, MachineState};
+use chip_eight::chip_eight_core::chip_eight::ChipEight;
+use chip_eight::chip_eight_core::debugger::{
+    self, Breakpoint, DebugHost, Debugger, RunUntil, Stop, Watch,
+};
+use chip_eight::chip_eight_core::error::EmulatorError;
+use chip_eight::chip_eight_core::expression::{Expression, Template};
+use chip_eight::chip_eight_core::keypad::{Key, Position};
+use chip_eight::common::hex_utils;
+use super::terminal;
+
+const HELP: &str = "\
+  break <addr> [if <expr>]
+                        stop when the PC gets to addr, and expr is true, e.g.
+                        `break 0x2A4 if v3 == 0 && [i] > 4`, without addr list breakpoints (b)
+  log <addr> <message>  print message each time the PC gets to addr, without stopping, with
+                        {expr} or {expr:x} filled in, e.g. `log 0x2A4 score {[i]} at {i:x}`
+  delete <addr>         remove a breakpoint or logpoint
+  watch write|read <addr> [end]
+                        stop when the program writes or reads memory in addr to end,
+                        running an instruction reads it
+  watch <reg> <value>   stop when V0 to VF changes to value
+  watch i <addr> [end]  stop when I changes to point into addr to end
+  watch                 list watchpoints
+  unwatch <n>           remove watchpoint n
+  step [n]              run n instructions, 1 by default (s)
+  next                  run one instruction, or a whole subroutine if it is a CALL (n)
+  finish                run until the current subroutine returns
+  frame [n]             run n frames, 1 by default (f)
+  continue              run until a breakpoint or a fault, Enter pauses (c)
+  regs                  registers and timers (r)
+  stack                 return addresses, top first
+  mem <addr> [len]      hex dump, 64 bytes by default (x)
+  disasm [addr] [n]     n instructions from addr, 10 from the PC by default (d)
+  set <reg> <value>     set V0 to VF, I or PC, e.g. `set v3 0x10`
+  key <key> down|up     press or release a key on the hex keypad, from the next frame on
+  screen                print the display
+  quit                  leave the debugger (q)
+Numbers are decimal, or hex with 0x. An empty line repeats the last step, next or frame.
+Expressions can use v0-vf, i, pc, sp, dt, st, [addr] for a byte and w[addr] for a word of
+memory, hits for the times the breakpoint has been reached, + - * / % & | ^ ~ << >>,
+== != < <= > >= and && || !.
+";
+const DISASSEMBLY_LINES: usize = 10;
+const DUMP_LENGTH: usize = 64;
+const DUMP_WIDTH: usize = 16;
+
+// Nothing is drawn while debugging, `screen` prints the framebuffer on demand.
+struct NoDisplay;
+
+impl Display for NoDisplay {
+    fn present(&mut self, _frame: &Frame) {}
+}
+
+// Lines typed at the prompt, put together from the chunks the input reader hands over.
+struct LineReader<'a> {
+    input: &'a Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl LineReader<'_> {
+    fn take_line(&mut self) -> Option<String> {
+        let end: usize = self.pending.iter().position(|byte| *byte == b'\n')?;
+        let line: Vec<u8> = self.pending.drain(..=end).collect();
+        return Some(String::from_utf8_lossy(&line).trim().to_string());
+    }
+    // Blocks until a line comes in, None once the input is closed.
+    fn next_line(&mut self) -> Option<String> {
+        loop {
+            if let Some(line) = self.take_line() { return Some(line); }
+            match self.input.recv() {
+                Ok(bytes) => self.pending.extend(bytes),
+                Err(_) if self.pending.is_empty() => return None,
+                Err(_) => self.pending.push(b'\n'),
+            }
+        }
+    }
+    // Whether a line has come in, without waiting. Closed input counts as one.
+    fn entered(&mut self) -> bool {
+        loop {
+            match self.input.try_recv() {
+                Ok(bytes) => self.pending.extend(bytes),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return true,
+            }
+        }
+        return self.take_line().is_some();
+    }
+}
+
+// Debug from the start, reading commands from stdin.
+pub fn run(chip_eight: &mut ChipEight) -> Result<(), Error> {
+    return debug(chip_eight, None, &terminal::spawn_input_reader());
+}
+
+// Take over a machine that is paused, e.g. by a fault, reading commands from the lines that
+// come in on `input`.
+pub fn debug(chip_eight: &mut ChipEight, fault: Option<EmulatorError>,
+        input: &Receiver<Vec<u8>>) -> Result<(), Error> {
+    chip_eight.set_display(Box::new(NoDisplay));
+    let mut lines: LineReader = LineReader { input, pending: Vec::new() };
+    let mut debugger: Debugger = Debugger::new();
+    println!("CHIP-8 debugger, `help` lists the commands.");
+    if let Some(error) = fault {
+        println!("fault: {}", error);
+    }
+    show_location(chip_eight, &debugger);
+
+    let mut repeat: Option<String> = None;
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next_line() else {
+            println!();
+            return Ok(());
+        };
+        let line: String = match (line.is_empty(), &repeat) {
+            (true, Some(previous)) => previous.clone(),
+            (true, None) => continue,
+            (false, _) => line,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        repeat = match words[0] {
+            "step" | "s" | "next" | "n" | "frame" | "f" => Some(line.clone()),
+            _ => None,
+        };
+        match command(chip_eight, &mut debugger, &words, &mut lines) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(message) => println!("{}", message),
+        }
+    }
+}
+
+// Carry out one command, true means quit.
+fn command(chip_eight: &mut ChipEight, debugger: &mut Debugger, words: &[&str],
+        lines: &mut LineReader) -> Result<bool, String> {
+    let number = |index: usize| -> Result<Option<u64>, String> {
+        return match words.get(index) {
+            Some(word) => hex_utils::parse_number(word).map(Some)
+                .ok_or_else(|| format!("expected a number, got '{}'", word)),
+            None => Ok(None),
+        };
+    };
+    let address = |index: usize| -> Result<Option<u16>, String> {
+        return match number(index)? {
+            Some(address @ 0..=0xFFF) => Ok(Some(address as u16)),
+            Some(address) => Err(format!("0x{:X} is outside memory", address)),
+            None => Ok(None),
+        };
+    };
+    let required = |value: Option<u16>| -> Result<u16, String> {
+        return value.ok_or_else(|| format!("{} needs an address", words[0]));
+    };
+    // How many steps or frames, at least one.
+    let count = |index: usize| -> Result<u32, String> {
+        let count: u64 = number(index)?.unwrap_or(1);
+        return u32::try_from(count).map(|count| count.max(1))
+            .map_err(|_| format!("{} is too many, the most is {}", count, u32::MAX));
+    };
+
+    let until: RunUntil = match words[0] {
+        "step" | "s" => RunUntil::Steps(count(1)?),
+        "next" | "n" => RunUntil::StepOver,
+        "finish" if chip_eight.state().stack_pointer == 0 =>
+            return Err("not in a subroutine".to_string()),
+        "finish" => RunUntil::Finish,
+        "frame" | "f" => RunUntil::Frames(count(1)?),
+        "continue" | "c" => {
+            println!("running, press Enter to pause");
+            RunUntil::Forever
+        }
+        "break" | "b" if words.len() > 2 => {
+            let address: u16 = required(address(1)?)?;
+            if words[2] != "if" || words.len() == 3 {
+                return Err("usage: break <addr> [if <expr>]".to_string());
+            }
+            let condition: Expression = Expression::parse(&words[3..].join(" "))
+                .map_err(|error| format!("bad condition, {}", error))?;
+            println!("breakpoint at 0x{:03X} if {}", address, condition);
+            debugger.set_breakpoint(address,
+                Breakpoint { condition: Some(condition), ..Breakpoint::default() });
+            return Ok(false);
+        }
+        "break" | "b" => {
+            match address(1)? {
+                Some(address) if debugger.add_breakpoint(address) =>
+                    println!("breakpoint at 0x{:03X}", address),
+                Some(address) => println!("there already is a breakpoint at 0x{:03X}", address),
+                None if debugger.breakpoints().next().is_none() => println!("no breakpoints"),
+                None => {
+                    for (address, breakpoint) in debugger.breakpoints() {
+                        println!("  0x{:03X} {}", address, breakpoint);
+                    }
+                }
+            }
+            return Ok(false);
+        }
+        "log" => {
+            let address: u16 = required(address(1)?)?;
+            if words.len() < 3 {
+                return Err("log needs a message".to_string());
+            }
+            let log: Template = Template::parse(&words[2..].join(" "))
+                .map_err(|error| format!("bad message, {}", error))?;
+            println!("logpoint at 0x{:03X}: {}", address, log);
+            debugger.set_breakpoint(address,
+                Breakpoint { log: Some(log), ..Breakpoint::default() });
+            return Ok(false);
+        }
+        "delete" => {
+            let address: u16 = required(address(1)?)?;
+            if !debugger.remove_breakpoint(address) {
+                return Err(format!("no breakpoint at 0x{:03X}", address));
+            }
+            return Ok(false);
+        }
+        "watch" if words.len() == 1 => {
+            if debugger.watchpoints().next().is_none() {
+                println!("no watchpoints");
+            }
+            for (number, watch) in debugger.watchpoints() {
+                println!("  {}: {}", number, watch);
+            }
+            return Ok(false);
+        }
+        "watch" => {
+            let watch: Watch = parse_watch(words)?;
+            let number: u32 = debugger.add_watchpoint(watch).map_err(|error| error.to_string())?;
+            println!("watchpoint {}: {}", number, watch);
+            return Ok(false);
+        }
+        "unwatch" => {
+            let number: u64 = number(1)?.ok_or("unwatch needs a watchpoint number")?;
+            if !u32::try_from(number).is_ok_and(|number| debugger.remove_watchpoint(number)) {
+                return Err(format!("no watchpoint {}", number));
+            }
+            return Ok(false);
+        }
+        "regs" | "r" => {
+            show_registers(&chip_eight.state());
+            return Ok(false);
+        }
+        "stack" => {
+            let state: MachineState = chip_eight.state();
+            if state.stack.is_empty() {
+                println!("(empty)");
+            }
+            for (level, address) in state.stack.iter().enumerate().rev() {
+                println!("  {:>2}: 0x{:03X}", level, address);
+            }
+            return Ok(false);
+        }
+        "mem" | "x" => {
+            let start: u16 = required(address(1)?)?;
+            let length: usize = number(2)?.unwrap_or(DUMP_LENGTH as u64) as usize;
+            show_memory(chip_eight.state().memory, start as usize, length)?;
+            return Ok(false);
+        }
+        "disasm" | "d" => {
+            let start: u16 = address(1)?.unwrap_or(chip_eight.program_counter());
+            let count: usize = number(2)?.unwrap_or(DISASSEMBLY_LINES as u64) as usize;
+            for address in (start..0xFFF).step_by(2).take(count) {
+                show_instruction(chip_eight, debugger, address);
+            }
+            return Ok(false);
+        }
+        "set" => {
+            set(chip_eight, words)?;
+            return Ok(false);
+        }
+        "key" => {
+            let key: Option<Key> = words.get(1)
+                .and_then(|word| u8::from_str_radix(word, 16).ok())
+                .and_then(Key::from_index);
+            let position: Option<Position> = match words.get(2) {
+                Some(&"down") => Some(Position::DOWN),
+                Some(&"up") => Some(Position::UP),
+                _ => None,
+            };
+            let (Some(key), Some(position)) = (key, position) else {
+                return Err("usage: key <0-F> down|up".to_string());
+            };
+            chip_eight.queue_key(key, position);
+            return Ok(false);
+        }
+        "screen" => {
+            show_screen(&chip_eight.framebuffer());
+            return Ok(false);
+        }
+        "help" | "h" => {
+            print!("{}", HELP);
+            return Ok(false);
+        }
+        "quit" | "q" => return Ok(true),
+        other => return Err(format!("unknown command '{}', try `help`", other)),
+    };
+
+    // Only runs with no set end can be paused, so a line typed ahead is not taken for Enter.
+    let pausable: bool = matches!(until, RunUntil::StepOver | RunUntil::Finish | RunUntil::Forever);
+    let stop: Stop = debugger.run(chip_eight, until, &mut Host { lines, pausable });
+    if stop != Stop::Done {
+        println!("{}", stop);
+    }
+    show_location(chip_eight, debugger);
+    return Ok(false);
+}
+
+// Lets a run be paused with Enter, and prints logpoints as they go off.
+struct Host<'a, 'b> {
+    lines: &'a mut LineReader<'b>,
+    pausable: bool,
+}
+
+impl DebugHost for Host<'_, '_> {
+    fn interrupted(&mut self) -> bool {
+        return self.pausable && self.lines.entered();
+    }
+    fn log(&mut self, address: u16, message: &str) {
+        println!("[0x{:03X}] {}", address, message);
+    }
+}
+
+// watch write|read|i <addr> [end], or watch <v0-vf> <value>
+fn parse_watch(words: &[&str]) -> Result<Watch, String> {
+    let usage: &str = "usage: watch write|read|i <addr> [end], or watch <v0-vf> <value>";
+    let target: String = words[1].to_ascii_lowercase();
+    let numbers: Vec<u64> = words[2..].iter()
+        .map(|word| hex_utils::parse_number(word)
+            .ok_or_else(|| format!("expected a number, got '{}'", word)))
+        .collect::<Result<_, _>>()?;
+    if let Some(register) = register_index(&target) {
+        let [value] = numbers[..] else { return Err(usage.to_string()); };
+        return Ok(Watch::Register { register, value: byte(value)? });
+    }
+    let (start, end): (u64, u64) = match numbers[..] {
+        [start] => (start, start),
+        [start, end] => (start, end),
+        _ => return Err(usage.to_string()),
+    };
+    if start > end || end > 0xFFF {
+        return Err(format!("expected a range within memory, got 0x{:X} to 0x{:X}", start, end));
+    }
+    let (start, end): (u16, u16) = (start as u16, end as u16);
+    return match target.as_str() {
+        "write" => Ok(Watch::Write { start, end }),
+        "read" => Ok(Watch::Read { start, end }),
+        "i" => Ok(Watch::Index { start, end }),
+        _ => Err(usage.to_string()),
+    };
+}
+
+// "v0" to "vf".
+fn register_index(name: &str) -> Option<usize> {
+    return name.strip_prefix('v')
+        .filter(|digit| digit.len() == 1)
+        .and_then(|digit| usize::from_str_radix(digit, 16).ok());
+}
+
+// set <v0-vf|i|pc> <value>
+fn set(chip_eight: &mut ChipEight, words: &[&str]) -> Result<(), String> {
+    let (Some(target), Some(value)) = (words.get(1), words.get(2)) else {
+        return Err("usage: set <v0-vf|i|pc> <value>".to_string());
+    };
+    let value: u64 = hex_utils::parse_number(value)
+        .ok_or_else(|| format!("expected a number, got '{}'", value))?;
+    let target: String = target.to_ascii_lowercase();
+    match (target.as_str(), register_index(&target)) {
+        (_, Some(register)) => chip_eight.set_register(register, byte(value)?)
+            .map_err(|error| error.to_string())?,
+        ("i", _) => chip_eight.set_index_register(word(value)?),
+        ("pc", _) => chip_eight.set_pc(word(value)?),
+        _ => return Err(format!("cannot set '{}', expected V0 to VF, I or PC", target)),
+    }
+    return Ok(());
+}
+
+fn byte(value: u64) -> Result<u8, String> {
+    return u8::try_from(value).map_err(|_| format!("{} does not fit in a byte", value));
+}
+
+fn word(value: u64) -> Result<u16, String> {
+    return u16::try_from(value).map_err(|_| format!("{} does not fit in 16 bits", value));
+}
+
+fn show_location(chip_eight: &ChipEight, debugger: &Debugger) {
+    if let Some(register) = chip_eight.state().waiting_for_key {
+        println!("waiting for a key to put in V{:X}", register);
+    }
+    show_instruction(chip_eight, debugger, chip_eight.program_counter());
+}
+
+// `=>` marks the PC and `*` a breakpoint.
+fn show_instruction(chip_eight: &ChipEight, debugger: &Debugger, address: u16) {
+    let marker: &str = if address == chip_eight.program_counter() {"=>"} else {"  "};
+    let breakpoint: &str = if debugger.is_breakpoint(address) {"*"} else {" "};
+    println!("{}{} 0x{:03X}: {}", marker, breakpoint, address,
+        debugger::describe_at(chip_eight, address));
+}
+
+fn show_registers(state: &MachineState) {
+    for (row, values) in state.registers.chunks(8).enumerate() {
+        let line: Vec<String> = values.iter().enumerate()
+            .map(|(i, value)| format!("V{:X}={:02X}", row * 8 + i, value))
+            .collect();
+        println!("  {}", line.join(" "));
+    }
+    println!("  I={:04X} PC={:04X} SP={} DT={:02X} ST={:02X}", state.index_register,
+        state.program_counter, state.stack_pointer, state.delay_timer, state.sound_timer);
+}
+
+fn show_memory(memory: &[u8], start: usize, length: usize) -> Result<(), String> {
+    if start >= memory.len() {
+        return Err(format!("0x{:X} is outside memory", start));
+    }
+    let end: usize = start.saturating_add(length).min(memory.len());
+    for row_start in (start..end).step_by(DUMP_WIDTH) {
+        let row: &[u8] = &memory[row_start..(row_start + DUMP_WIDTH).min(end)];
+        let hex: Vec<String> = row.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let text: String = row.iter()
+            .map(|byte| if byte.is_ascii_graphic() {*byte as char} else {'.'})
+            .collect();
+        println!("  {:03X}: {:<47}  |{}|", row_start, hex.join(" "), text);
+    }
+    return Ok(());
+}
+
+// Two pixels to a character, one above the other.
+fn show_screen(frame: &Frame) {
+    for y in (0..frame.height).step_by(2) {
+        let line: String = (0..frame.width).map(|x| {
+            match (frame.pixel(x, y), y + 1 < frame.height && frame.pixel(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            }
+        }).collect();
+        println!("|{}|", line);
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+use std::io::Error;
+
+use chip_eight::chip_eight_core::api::StepOutcome;
+use chip_eight::chip_eight_core::builder::ChipEightBuilder;
+use chip_eight::chip_eight_core::chip_eight::ChipEight;
+use chip_eight::chip_eight_core::error::EmulatorError;
+use chip_eight::chip_eight_core::movie::{self, Movie, PlaybackResult};
+use chip_eight::chip_eight_core::quirks::Quirks;
+use chip_eight::chip_eight_core::random::RandomMode;
+use chip_eight::chip_eight_core::scheduler::{Pacing, Speed};
+use chip_eight::common::image_utils::{self, Palette};
+
+pub struct HeadlessOptions {
+    // Upper bound on the number of instructions to execute.
+    pub cycles: usize,
+    pub quirks: Quirks,
+    // Picked at random when not given.
+    pub seed: Option<u64>,
+    pub random_mode: RandomMode,
+    pub speed: Speed,
+    pub scale: usize,
+    pub palette: Palette,
+    // Where to write the final frame, the extension picks the format (.ppm or .pbm).
+    pub output: String,
+    // Where to save a crash report if the ROM faults, it always goes to stderr.
+    pub crash_report: Option<String>,
+}
+
+impl HeadlessOptions {
+    pub fn new(output: &str) -> HeadlessOptions {
+        HeadlessOptions {
+            cycles: 1_000_000,
+            quirks: Quirks::new(),
+            seed: None,
+            random_mode: RandomMode::Xorshift,
+            speed: Speed::InstructionsPerSecond(700),
+            scale: 1,
+            palette: Palette::monochrome(),
+            output: output.to_string(),
+            crash_report: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    CycleLimit,
+    // The program counter did not move, e.g. the `1NNN` jump-to-self most ROMs end with.
+    Looped { address: u16 },
+    // FX0A is waiting on a key nobody is going to press.
+    WaitingForKey { address: u16 },
+    Fault(EmulatorError),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::CycleLimit => write!(f, "ran out of cycles"),
+            StopReason::Looped { address } => write!(f, "looped at 0x{:03X}", address),
+            StopReason::WaitingForKey { address } =>
+                write!(f, "waiting for a key at 0x{:03X}", address),
+            StopReason::Fault(error) => write!(f, "fault: {}", error),
+        }
+    }
+}
+
+impl StopReason {
+    // Why `test` fails a run that stopped this way, if it does. A ROM that ends in a loop has
+    // finished, one waiting on a key has not, unless the image it left is what is being checked.
+    pub fn problem(self, checking_image: bool) -> Option<String> {
+        return match self {
+            StopReason::Fault(error) => Some(format!("fault: {}", error)),
+            StopReason::WaitingForKey { address } if !checking_image =>
+                Some(format!("stuck waiting for a key at 0x{:03X}", address)),
+            _ => None,
+        };
+    }
+}
+
+pub struct HeadlessReport {
+    pub cycles: usize,
+    pub reason: StopReason,
+}
+
+// Hands the machine back too, for a debugger to take over after a fault.
+pub fn run(rom: &[u8], options: &HeadlessOptions) -> Result<(ChipEight, HeadlessReport), Error> {
+    let (chip_eight, report) = execute(rom, options)?;
+    image_utils::write_frame(&options.output, &chip_eight.framebuffer(), options.scale,
+        options.palette)?;
+    return Ok((chip_eight, report));
+}
+
+// Run until the ROM stops making progress, leaving the machine to be inspected. Any fault is
+// reported as for `run`, but the image is left to the caller.
+pub fn execute(rom: &[u8], options: &HeadlessOptions)
+        -> Result<(ChipEight, HeadlessReport), Error> {
+    // Nothing to show on, the framebuffer is read back once the run is over. Timers still run
+    // at 60 Hz of emulated time, just not held to the wall clock.
+    let mut builder: ChipEightBuilder = ChipEightBuilder::new()
+        .quirks(options.quirks)
+        .random_mode(options.random_mode)
+        .speed(options.speed)
+        .pacing(Pacing::Turbo);
+    if let Some(seed) = options.seed {
+        builder = builder.seed(seed);
+    }
+    let mut chip_eight: ChipEight = builder.build();
+
+    let mut report: HeadlessReport = HeadlessReport { cycles: 0, reason: StopReason::CycleLimit };
+    if let Err(error) = chip_eight.load_rom(rom) {
+        report.reason = StopReason::Fault(error);
+    }
+    while report.cycles < options.cycles && report.reason == StopReason::CycleLimit {
+        let outcome: StepOutcome = match chip_eight.step() {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                report.reason = StopReason::Fault(error);
+                break;
+            }
+        };
+        report.cycles += 1;
+        // Only an instruction that was run and left the PC where it was is a loop, a step held
+        // up by FX0A, the display wait or an idle frame does not move the PC either.
+        let StepOutcome::Executed { pc, .. } = outcome else { continue; };
+        if chip_eight.is_waiting_for_key() {
+            report.reason = StopReason::WaitingForKey { address: pc };
+            break;
+        }
+        if chip_eight.program_counter() == pc {
+            report.reason = StopReason::Looped { address: pc };
+            break;
+        }
+    }
+
+    if let StopReason::Fault(error) = report.reason {
+        chip_eight.crash_report(error).write(options.crash_report.as_deref())?;
+    }
+    return Ok((chip_eight, report));
+}
+
+// Play a recorded movie back as fast as possible, checking it stays in sync with the recording.
+// Only writes the final frame when given somewhere to put it.
+pub fn replay(rom: &[u8], movie: &[u8], output: Option<&str>) -> Result<PlaybackResult, Error> {
+    let movie: Movie = Movie::from_bytes(movie).map_err(Error::other)?;
+    let mut chip_eight: ChipEight = ChipEightBuilder::new().pacing(Pacing::Turbo).build();
+    chip_eight.load_rom(rom).map_err(Error::other)?;
+    let result: PlaybackResult = movie::play(&mut chip_eight, &movie).map_err(Error::other)?;
+
+    if let PlaybackResult::Fault { error, .. } = result {
+        chip_eight.crash_report(error).write(None)?;
+    }
+    if let Some(output) = output {
+        image_utils::write_frame(output, &chip_eight.framebuffer(), 1, Palette::monochrome())?;
+    }
+    return Ok(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(rom: &[u8]) -> StopReason {
+        let mut options: HeadlessOptions = HeadlessOptions::new("unused.ppm");
+        options.cycles = 1000;
+        options.seed = Some(0);
+        return execute(rom, &options).unwrap().1.reason;
+    }
+
+    #[test]
+    fn loops_and_cycle_limits_pass() {
+        // V0 = 1, then jump to self.
+        let reason: StopReason = stop(&[0x60, 0x01, 0x12, 0x02]);
+        assert_eq!(reason, StopReason::Looped { address: 0x202 });
+        assert_eq!(reason.problem(false), None);
+        // V0 += 1, jump back, forever.
+        let reason: StopReason = stop(&[0x70, 0x01, 0x12, 0x00]);
+        assert_eq!(reason, StopReason::CycleLimit);
+        assert_eq!(reason.problem(false), None);
+    }
+
+    #[test]
+    fn waiting_for_a_key_fails_unless_the_image_is_checked() {
+        let reason: StopReason = stop(&[0x00, 0xE0, 0xF0, 0x0A]);
+        assert_eq!(reason, StopReason::WaitingForKey { address: 0x202 });
+        assert_eq!(reason.problem(false), Some("stuck waiting for a key at 0x202".to_string()));
+        assert_eq!(reason.problem(true), None);
+    }
+
+    #[test]
+    fn faults_always_fail() {
+        // RET with nothing to return to.
+        let reason: StopReason = stop(&[0x00, 0xEE]);
+        assert_eq!(reason, StopReason::Fault(EmulatorError::StackUnderflow { pc: 0x200 }));
+        assert_eq!(reason.problem(true), Some("fault: stack underflow at 200".to_string()));
+    }
+}
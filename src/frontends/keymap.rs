@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use chip_eight::chip_eight_core::keypad::Key;
+use chip_eight::common::hash_utils;
+
+// A key on the host keyboard, as far as a terminal can tell them apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HostKey {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl HostKey {
+    // A single character, quoted as "=" or '#' if need be, or one of: space, enter, tab, up,
+    // down, left, right.
+    pub fn from_name(name: &str) -> Option<HostKey> {
+        let name: &str = match quoted_name(name) {
+            Some(quoted) if quoted.len() == name.len() => &name[1..name.len() - 1],
+            _ => name,
+        };
+        let mut chars = name.chars();
+        if let (Some(single), None) = (chars.next(), chars.next()) {
+            return Some(HostKey::Char(lowercase(single)));
+        }
+        return match name.to_ascii_lowercase().as_str() {
+            "space" => Some(HostKey::Char(' ')),
+            "enter" => Some(HostKey::Char('\r')),
+            "tab" => Some(HostKey::Char('\t')),
+            "up" => Some(HostKey::Up),
+            "down" => Some(HostKey::Down),
+            "left" => Some(HostKey::Left),
+            "right" => Some(HostKey::Right),
+            _ => None,
+        };
+    }
+}
+
+fn lowercase(character: char) -> char {
+    return character.to_lowercase().next().unwrap_or(character);
+}
+
+// A single character between matching quotes at the start of `text`, e.g. "=" or '#'.
+fn quoted_name(text: &str) -> Option<&str> {
+    let mut chars = text.char_indices();
+    let (_, quote) = chars.next().filter(|(_, quote)| matches!(quote, '"' | '\''))?;
+    chars.next()?;
+    let (end, _) = chars.next().filter(|(_, close)| *close == quote)?;
+    return Some(&text[..end + 1]);
+}
+
+// Each preset puts the COSMAC VIP hex keypad on the same physical 4x4 block:
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+const KEYPAD_ORDER: [Key; 16] = [
+    Key::ONE, Key::TWO, Key::THREE, Key::C,
+    Key::FOUR, Key::FIVE, Key::SIX, Key::D,
+    Key::SEVEN, Key::EIGHT, Key::NINE, Key::E,
+    Key::A, Key::ZERO, Key::B, Key::F,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Qwerty,
+    Azerty,
+    Qwertz,
+    Dvorak,
+    // 7 8 9 / on top, down to 0 . Enter + at the bottom.
+    Numpad,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 5] =
+        [Layout::Qwerty, Layout::Azerty, Layout::Qwertz, Layout::Dvorak, Layout::Numpad];
+
+    pub fn name(self) -> &'static str {
+        return match self {
+            Layout::Qwerty => "qwerty",
+            Layout::Azerty => "azerty",
+            Layout::Qwertz => "qwertz",
+            Layout::Dvorak => "dvorak",
+            Layout::Numpad => "numpad",
+        };
+    }
+    pub fn from_name(name: &str) -> Option<Layout> {
+        return Layout::ALL.into_iter().find(|layout| layout.name() == name);
+    }
+    // The host keys on the 4x4 block, in `KEYPAD_ORDER`.
+    fn block(self) -> &'static str {
+        return match self {
+            Layout::Qwerty => "1234qwerasdfzxcv",
+            Layout::Azerty => "1234azerqsdfwxcv",
+            Layout::Qwertz => "1234qwerasdfyxcv",
+            Layout::Dvorak => "1234',.paoeu;qjk",
+            Layout::Numpad => "789/456*123-0.\r+",
+        };
+    }
+    pub fn keymap(self) -> Keymap {
+        let mut keymap: Keymap = Keymap { bindings: HashMap::new() };
+        for (host, key) in self.block().chars().zip(KEYPAD_ORDER) {
+            keymap.bind(HostKey::Char(host), key);
+        }
+        if self == Layout::Azerty {
+            // The number row needs shift on AZERTY, so take what it types without it as well.
+            for (host, key) in "&é\"'".chars().zip(KEYPAD_ORDER) {
+                keymap.bind(HostKey::Char(host), key);
+            }
+        }
+        return keymap;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: HashMap<HostKey, Key>,
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        return Layout::Qwerty.keymap();
+    }
+    // A host key drives a single CHIP-8 key, but a CHIP-8 key can have several host keys.
+    pub fn bind(&mut self, host: HostKey, key: Key) {
+        self.bindings.insert(host, key);
+    }
+    pub fn key(&self, host: HostKey) -> Option<Key> {
+        let host: HostKey = match host {
+            HostKey::Char(character) => HostKey::Char(lowercase(character)),
+            _ => host,
+        };
+        return self.bindings.get(&host).copied();
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Rule {
+    // Start over from a preset.
+    Layout(Layout),
+    Bind(HostKey, Key),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeymapError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for KeymapError {}
+
+// The keymap config file:
+//
+//   # Anything before the first section applies to every ROM.
+//   layout = azerty
+//   space = 5
+//
+//   # Overrides for the ROM with this SHA-1, or any prefix of it.
+//   [3f0a9c1e]
+//   left = 4
+//   right = 6
+//   "=" = C
+//
+// `layout` starts over from a preset, every other line binds a host key (see
+// `HostKey::from_name`) to a CHIP-8 key given as a hex digit. Quote `=` and `#` to bind them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeymapConfig {
+    rules: Vec<Rule>,
+    roms: Vec<(String, Vec<Rule>)>,
+}
+
+impl KeymapConfig {
+    pub fn new() -> KeymapConfig {
+        KeymapConfig { rules: Vec::new(), roms: Vec::new() }
+    }
+    pub fn parse(text: &str, file: &str) -> Result<KeymapConfig, KeymapError> {
+        let mut config: KeymapConfig = KeymapConfig::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| KeymapError { file: file.to_string(), line: index + 1,
+                message };
+            // A `#` starts a comment, unless it is the quoted key being bound.
+            let line: &str = line.trim();
+            let quoted: usize = quoted_name(line).map_or(0, str::len);
+            let line: &str = match line[quoted..].find('#') {
+                Some(comment) => line[..quoted + comment].trim(),
+                None => line,
+            };
+            if line.is_empty() { continue; }
+
+            if let Some(section) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let prefix: String = section.trim().to_ascii_lowercase();
+                if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(error(format!("expected a ROM SHA-1 in [...], got '{}'", section)));
+                }
+                config.roms.push((prefix, Vec::new()));
+                continue;
+            }
+            let name_end: Option<usize> = if quoted > 0 {Some(quoted)} else {line.find('=')};
+            let Some((name, value)) = name_end
+                .and_then(|end| Some((&line[..end], line[end..].trim_start().strip_prefix('=')?)))
+            else {
+                return Err(error(format!("expected 'key = value', got '{}'", line)));
+            };
+            let (name, value) = (name.trim(), value.trim());
+            let rule: Rule = if name == "layout" {
+                Rule::Layout(Layout::from_name(value)
+                    .ok_or_else(|| error(format!("unknown layout '{}'", value)))?)
+            } else {
+                let host: HostKey = HostKey::from_name(name)
+                    .ok_or_else(|| error(format!("unknown host key '{}'", name)))?;
+                let key: Key = u8::from_str_radix(value, 16).ok().and_then(Key::from_index)
+                    .ok_or_else(|| error(format!("expected a key from 0 to F, got '{}'", value)))?;
+                Rule::Bind(host, key)
+            };
+            match config.roms.last_mut() {
+                Some((_, rules)) => rules.push(rule),
+                None => config.rules.push(rule),
+            }
+        }
+        return Ok(config);
+    }
+    pub fn load(path: &str) -> Result<KeymapConfig, KeymapError> {
+        return match fs::read_to_string(path) {
+            Ok(text) => KeymapConfig::parse(&text, path),
+            Err(reason) => Err(KeymapError { file: path.to_string(), line: 0,
+                message: format!("could not read file: {}", reason) }),
+        };
+    }
+    // The global rules, then those of every section matching the ROM.
+    pub fn keymap_for(&self, rom_sha1: &[u8; 20]) -> Keymap {
+        let sha1: String = hash_utils::to_hex(rom_sha1).to_ascii_lowercase();
+        let mut keymap: Keymap = Keymap::new();
+        let overrides = self.roms.iter()
+            .filter(|(prefix, _)| sha1.starts_with(prefix.as_str()))
+            .flat_map(|(_, rules)| rules);
+        for rule in self.rules.iter().chain(overrides) {
+            match *rule {
+                Rule::Layout(layout) => keymap = layout.keymap(),
+                Rule::Bind(host, key) => keymap.bind(host, key),
+            }
+        }
+        return keymap;
+    }
+}
+
+// `$XDG_CONFIG_HOME/chip_eight/keymap.conf`, falling back to `~/.config`.
+pub fn default_config_path() -> Option<PathBuf> {
+    let directory: PathBuf = match env::var_os("XDG_CONFIG_HOME") {
+        Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    return Some(directory.join("chip_eight").join("keymap.conf"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_cover_the_same_block() {
+        for layout in Layout::ALL {
+            let keymap: Keymap = layout.keymap();
+            for (host, key) in layout.block().chars().zip(KEYPAD_ORDER) {
+                assert_eq!(keymap.key(HostKey::Char(host)), Some(key), "{}", layout.name());
+            }
+        }
+        let qwerty: Keymap = Layout::Qwerty.keymap();
+        assert_eq!(qwerty.key(HostKey::Char('x')), Some(Key::ZERO));
+        assert_eq!(qwerty.key(HostKey::Char('V')), Some(Key::F));
+        assert_eq!(qwerty.key(HostKey::Char('p')), None);
+        assert_eq!(Layout::Azerty.keymap().key(HostKey::Char('é')), Some(Key::TWO));
+        assert_eq!(Layout::Numpad.keymap().key(HostKey::Char('\r')), Some(Key::B));
+    }
+
+    #[test]
+    fn names_host_keys() {
+        assert_eq!(HostKey::from_name("Q"), Some(HostKey::Char('q')));
+        assert_eq!(HostKey::from_name("enter"), Some(HostKey::Char('\r')));
+        assert_eq!(HostKey::from_name("LEFT"), Some(HostKey::Left));
+        assert_eq!(HostKey::from_name("\"=\""), Some(HostKey::Char('=')));
+        assert_eq!(HostKey::from_name("'#'"), Some(HostKey::Char('#')));
+        assert_eq!(HostKey::from_name("'\"'"), Some(HostKey::Char('"')));
+        assert_eq!(HostKey::from_name("\"ab\""), None);
+        assert_eq!(HostKey::from_name("escape"), None);
+    }
+
+    #[test]
+    fn parses_bindings_comments_and_sections() {
+        let text: &str = "\
+# a comment
+layout = dvorak   # trailing comment
+space = 5
+\"=\" = c  # bind =
+'#' = D
+[AB12]
+up = 2
+";
+        let config: KeymapConfig = KeymapConfig::parse(text, "keymap.conf").unwrap();
+        assert_eq!(config.rules, [
+            Rule::Layout(Layout::Dvorak),
+            Rule::Bind(HostKey::Char(' '), Key::FIVE),
+            Rule::Bind(HostKey::Char('='), Key::C),
+            Rule::Bind(HostKey::Char('#'), Key::D),
+        ]);
+        assert_eq!(config.roms, [("ab12".to_string(), vec![Rule::Bind(HostKey::Up, Key::TWO)])]);
+    }
+
+    #[test]
+    fn reports_the_bad_line() {
+        let fail = |text: &str| KeymapConfig::parse(text, "keymap.conf").unwrap_err();
+        let error: KeymapError = fail("x = 1\n\nlayout = colemak");
+        assert_eq!((error.line, error.message.as_str()), (3, "unknown layout 'colemak'"));
+        assert_eq!(error.to_string(), "keymap.conf:3: unknown layout 'colemak'");
+        assert_eq!(fail("x 1").message, "expected 'key = value', got 'x 1'");
+        assert_eq!(fail("x = 10").message, "expected a key from 0 to F, got '10'");
+        assert_eq!(fail("escape = 1").message, "unknown host key 'escape'");
+        assert_eq!(fail("\"=\" 1").message, "expected 'key = value', got '\"=\" 1'");
+        assert_eq!(fail("[xyz]").message, "expected a ROM SHA-1 in [...], got 'xyz'");
+        // Unquoted, `#` starts a comment and `=` ends the name.
+        assert_eq!(fail("# = 1\n= = 2").line, 2);
+    }
+
+    #[test]
+    fn rom_sections_override_the_global_rules() {
+        let text: &str = "\
+x = 1
+q = 2
+[a9993e]
+x = 3
+[a999]
+layout = numpad
+q = 4
+[ffff]
+x = 5
+";
+        let config: KeymapConfig = KeymapConfig::parse(text, "keymap.conf").unwrap();
+        // SHA-1 of "abc" starts with a9993e.
+        let matching: Keymap = config.keymap_for(&hash_utils::sha1(b"abc"));
+        assert_eq!(matching.key(HostKey::Char('x')), None);
+        assert_eq!(matching.key(HostKey::Char('q')), Some(Key::FOUR));
+        assert_eq!(matching.key(HostKey::Char('7')), Some(Key::ONE));
+
+        let other: Keymap = config.keymap_for(&hash_utils::sha1(b"abcd"));
+        assert_eq!(other.key(HostKey::Char('x')), Some(Key::ONE));
+        assert_eq!(other.key(HostKey::Char('q')), Some(Key::TWO));
+        assert_eq!(other.key(HostKey::Char('w')), Some(Key::FIVE));
+    }
+}
@@ -0,0 +1,4 @@
+pub mod headless;
+pub mod terminal;
+pub mod keymap;
+pub mod debugger;
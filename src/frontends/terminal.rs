@@ -0,0 +1,458 @@
+use std::fs;
+use std::io::{self, Error, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chip_eight::chip_eight_core::api::{Display, Frame};
+use chip_eight::chip_eight_core::builder::ChipEightBuilder;
+use chip_eight::chip_eight_core::chip_eight::ChipEight;
+use chip_eight::chip_eight_core::crash_report::CrashReport;
+use chip_eight::chip_eight_core::error::EmulatorError;
+use chip_eight::chip_eight_core::graphics::HEIGHT;
+use chip_eight::chip_eight_core::keypad::{Key, KeyEvent, Position};
+use super::debugger;
+use super::keymap::{HostKey, Keymap, KeymapConfig};
+use chip_eight::chip_eight_core::movie;
+use chip_eight::chip_eight_core::quirks::Quirks;
+use chip_eight::chip_eight_core::random::RandomMode;
+use chip_eight::chip_eight_core::rewind::{self, Rewind};
+use chip_eight::chip_eight_core::save_state::{self, SLOTS};
+use chip_eight::chip_eight_core::scheduler::{Pacing, Speed};
+use chip_eight::common::image_utils::Palette;
+
+const ENTER_ALTERNATE_SCREEN: &str = "\x1b[?1049h\x1b[?25l\x1b[2J";
+const LEAVE_ALTERNATE_SCREEN: &str = "\x1b[?25h\x1b[?1049l";
+
+const CTRL_C: u8 = 0x03;
+const ESCAPE: u8 = 0x1B;
+const BACKSPACE: u8 = 0x7F;
+
+// Function keys as sent by xterm compatible terminals.
+const F5: &[u8] = b"\x1b[15~";
+const F6: &[u8] = b"\x1b[17~";
+const F7: &[u8] = b"\x1b[18~";
+const F8: &[u8] = b"\x1b[19~";
+
+pub struct TerminalOptions {
+    pub quirks: Quirks,
+    // Picked at random when not given.
+    pub seed: Option<u64>,
+    pub random_mode: RandomMode,
+    pub speed: Speed,
+    pub pacing: Pacing,
+    // Terminals only report key presses (and auto-repeats), so a key is released once it
+    // has not been seen for this long.
+    pub key_release_timeout: Duration,
+    // Where to save a crash report if the ROM faults, it always goes to stderr. Every fault gets
+    // one, those rewound or loaded out of included, the file keeps the last.
+    pub crash_report: Option<String>,
+    // Drop into the debugger as soon as the ROM faults, rather than showing the fault in the
+    // game for it to be rewound or loaded out of.
+    pub debug_on_fault: bool,
+    // Where F5 saves and F7 loads the numbered save state slots.
+    pub save_directory: PathBuf,
+    // Bytes of history kept for rewinding with Backspace, zero turns it off.
+    pub rewind_budget: usize,
+    // Record the session as a movie, written out on quitting.
+    pub record: Option<PathBuf>,
+    // Host keys for the hex keypad, QWERTY unless the config says otherwise.
+    pub keymap: KeymapConfig,
+    // Character cells per pixel across, and half cells down.
+    pub scale: usize,
+    // The terminal's own colours when not given.
+    pub palette: Option<Palette>,
+}
+
+impl TerminalOptions {
+    pub fn new() -> TerminalOptions {
+        TerminalOptions {
+            quirks: Quirks::new(),
+            seed: None,
+            random_mode: RandomMode::Xorshift,
+            speed: Speed::InstructionsPerSecond(700),
+            pacing: Pacing::RealTime,
+            key_release_timeout: Duration::from_millis(250),
+            crash_report: None,
+            debug_on_fault: true,
+            save_directory: PathBuf::from("."),
+            rewind_budget: rewind::DEFAULT_BUDGET,
+            record: None,
+            keymap: KeymapConfig::new(),
+            scale: 1,
+            palette: None,
+        }
+    }
+}
+
+// Renders two pixel rows per character cell with half blocks, only redrawing changed cells.
+pub struct TerminalDisplay {
+    cells: Vec<char>,
+    scale: usize,
+    palette: Option<Palette>,
+}
+
+impl TerminalDisplay {
+    pub fn new(scale: usize, palette: Option<Palette>) -> TerminalDisplay {
+        TerminalDisplay { cells: Vec::new(), scale: scale.max(1), palette }
+    }
+}
+
+// Rows of character cells the screen takes up, the status lines go underneath.
+fn screen_rows(scale: usize) -> usize {
+    return (HEIGHT * scale.max(1)).div_ceil(2);
+}
+
+fn half_block(top: bool, bottom: bool) -> char {
+    return match (top, bottom) {
+        (true, true) => '█',
+        (true, false) => '▀',
+        (false, true) => '▄',
+        (false, false) => ' ',
+    };
+}
+
+impl Display for TerminalDisplay {
+    fn present(&mut self, frame: &Frame) {
+        // Work in scaled pixels, each frame pixel covering `scale` of them each way.
+        let scale: usize = self.scale;
+        let (width, height) = (frame.width * scale, frame.height * scale);
+        let pixel = |x: usize, y: usize| y < height && frame.pixel(x / scale, y / scale);
+        let rows: usize = height.div_ceil(2);
+        if self.cells.len() != width * rows {
+            // Nothing on screen yet, seed with a value that never matches so everything is drawn.
+            self.cells = vec!['\0'; width * rows];
+        }
+
+        let mut output: String = String::new();
+        let mut changed: bool = false;
+        if let Some(Palette { on: [r, g, b], off: [br, bg, bb] }) = self.palette {
+            output.push_str(&format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                r, g, b, br, bg, bb));
+        }
+        let dirty_rows = (frame.dirty.y * scale / 2)
+            ..((frame.dirty.y + frame.dirty.height) * scale).div_ceil(2);
+        for row in dirty_rows {
+            // Index of the last cell written, so adjacent changes skip the cursor move.
+            let mut cursor: Option<usize> = None;
+            for column in (frame.dirty.x * scale)..((frame.dirty.x + frame.dirty.width) * scale) {
+                let cell: char = half_block(pixel(column, row * 2), pixel(column, row * 2 + 1));
+                let index: usize = column + row * width;
+                if self.cells[index] == cell { continue; }
+
+                self.cells[index] = cell;
+                changed = true;
+                if cursor != Some(column) {
+                    output.push_str(&format!("\x1b[{};{}H", row + 1, column + 1));
+                }
+                output.push(cell);
+                cursor = Some(column + 1);
+            }
+        }
+        if changed {
+            output.push_str("\x1b[0m");
+            let mut stdout = io::stdout().lock();
+            let _ = stdout.write_all(output.as_bytes());
+            let _ = stdout.flush();
+        }
+    }
+}
+
+// Puts the terminal into raw mode on the alternate screen, and restores it when dropped so a
+// panic mid-game does not leave the shell unusable.
+struct RawTerminal {
+    saved_settings: String,
+}
+
+impl RawTerminal {
+    fn enter() -> Result<RawTerminal, Error> {
+        let saved_settings: String = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        print!("{}", ENTER_ALTERNATE_SCREEN);
+        io::stdout().flush()?;
+        return Ok(RawTerminal { saved_settings: saved_settings.trim().to_string() });
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("{}", LEAVE_ALTERNATE_SCREEN);
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved_settings]);
+    }
+}
+
+fn stty(arguments: &[&str]) -> Result<String, Error> {
+    let output = Command::new("stty")
+        .args(arguments)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+}
+
+// Sends whatever each read returns, so an escape sequence arrives in one piece and a lone
+// Escape can be told apart from the start of one.
+pub fn spawn_input_reader() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut buffer: [u8; 32] = [0; 32];
+        while let Ok(count @ 1..) = stdin.read(&mut buffer) {
+            if sender.send(buffer[..count].to_vec()).is_err() { break; }
+        }
+    });
+    return receiver;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Input {
+    Quit,
+    Key(Key),
+    SaveState,
+    NextSlot,
+    LoadState,
+    Rewind,
+    Debug,
+}
+
+fn parse_input(bytes: &[u8], keymap: &Keymap) -> Vec<Input> {
+    let mut inputs: Vec<Input> = Vec::new();
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        let rest: &[u8] = &bytes[i..];
+        if rest[0] == ESCAPE && rest.len() > 1 {
+            // A control sequence runs up to its final byte, anything not bound is dropped.
+            let length: usize = match rest[1] {
+                b'[' => rest[2..].iter().position(|byte| (0x40..=0x7E).contains(byte))
+                    .map_or(rest.len(), |end| end + 3),
+                b'O' => rest.len().min(3),
+                // Alt held down with another key.
+                _ => 2,
+            };
+            let host: Option<HostKey> = match &rest[..length] {
+                F5 => { inputs.push(Input::SaveState); None }
+                F6 => { inputs.push(Input::NextSlot); None }
+                F7 => { inputs.push(Input::LoadState); None }
+                F8 => { inputs.push(Input::Debug); None }
+                // Arrow keys, in both normal and application cursor mode.
+                b"\x1b[A" | b"\x1bOA" => Some(HostKey::Up),
+                b"\x1b[B" | b"\x1bOB" => Some(HostKey::Down),
+                b"\x1b[C" | b"\x1bOC" => Some(HostKey::Right),
+                b"\x1b[D" | b"\x1bOD" => Some(HostKey::Left),
+                _ => None,
+            };
+            if let Some(key) = host.and_then(|host| keymap.key(host)) {
+                inputs.push(Input::Key(key));
+            }
+            i += length;
+            continue;
+        }
+        let reserved: Option<Input> = match rest[0] {
+            CTRL_C | ESCAPE => Some(Input::Quit),
+            BACKSPACE | 0x08 => Some(Input::Rewind),
+            _ => None,
+        };
+        if let Some(input) = reserved {
+            inputs.push(input);
+            i += 1;
+            continue;
+        }
+        // Characters outside ASCII, like the é on an AZERTY number row, take several bytes.
+        let length: usize = match rest[0] {
+            0xF0.. => 4,
+            0xE0.. => 3,
+            0xC0.. => 2,
+            _ => 1,
+        }.min(rest.len());
+        let character: Option<char> =
+            std::str::from_utf8(&rest[..length]).ok().and_then(|text| text.chars().next());
+        if let Some(key) = character.and_then(|character| keymap.key(HostKey::Char(character))) {
+            inputs.push(Input::Key(key));
+        }
+        i += length;
+    }
+    return inputs;
+}
+
+// The line under the screen, and the fault report under that.
+struct StatusLines {
+    row: usize,
+}
+
+impl StatusLines {
+    fn new(scale: usize) -> StatusLines {
+        StatusLines { row: screen_rows(scale) + 1 }
+    }
+    fn show(&self, message: &str) {
+        print!("\x1b[{};1H\x1b[2K {}", self.row, message);
+        let _ = io::stdout().flush();
+    }
+    fn show_fault(&self, error: &EmulatorError) {
+        print!("\x1b[{};1H\x1b[7m FAULT \x1b[0m {}\r\n Backspace rewind  F7 load  F8 debug  \
+            Esc quit", self.row + 1, error);
+        let _ = io::stdout().flush();
+    }
+    fn clear_fault(&self) {
+        print!("\x1b[{};1H\x1b[2K\x1b[{};1H\x1b[2K", self.row + 1, self.row + 2);
+        let _ = io::stdout().flush();
+    }
+}
+
+// How the player left the game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Exit {
+    Quit,
+    // F8 or a fault, to carry on in the debugger from where the machine is.
+    Debug,
+}
+
+fn slot_path(chip_eight: &ChipEight, options: &TerminalOptions, slot: u8) -> PathBuf {
+    return save_state::slot_path(&options.save_directory, &chip_eight.rom_sha1(), slot);
+}
+
+fn save_slot(chip_eight: &ChipEight, options: &TerminalOptions, slot: u8) -> Result<(), Error> {
+    return fs::write(slot_path(chip_eight, options, slot), chip_eight.save_state());
+}
+
+fn load_slot(chip_eight: &mut ChipEight, options: &TerminalOptions, slot: u8)
+        -> Result<(), Error> {
+    let state: Vec<u8> = fs::read(slot_path(chip_eight, options, slot))?;
+    return chip_eight.load_state(&state).map_err(Error::other);
+}
+
+pub fn run(rom: &[u8], options: &TerminalOptions) -> Result<(), Error> {
+    let display: TerminalDisplay = TerminalDisplay::new(options.scale, options.palette);
+    let mut builder: ChipEightBuilder = ChipEightBuilder::new()
+        .display(Box::new(display))
+        .quirks(options.quirks)
+        .random_mode(options.random_mode)
+        .speed(options.speed)
+        .pacing(options.pacing);
+    if let Some(seed) = options.seed {
+        builder = builder.seed(seed);
+    }
+    let mut chip_eight: ChipEight = builder.build();
+    chip_eight.load_rom(rom).map_err(Error::other)?;
+    if options.record.is_some() {
+        chip_eight.start_recording(movie::CHECKSUM_INTERVAL);
+    }
+
+    // Reports are taken as faults happen and written once the terminal is back to normal, then
+    // the debugger takes over the machine where it stopped on a fault or if the player asked.
+    let input: Receiver<Vec<u8>> = spawn_input_reader();
+    let mut reports: Vec<CrashReport> = Vec::new();
+    let (exit, fault): (Exit, Option<EmulatorError>) =
+        play(&mut chip_eight, options, &input, &mut reports)?;
+    if let (Some(path), Some(movie)) = (&options.record, chip_eight.stop_recording()) {
+        fs::write(path, movie.to_bytes())?;
+    }
+    for report in &reports {
+        report.write(options.crash_report.as_deref())?;
+    }
+    if exit == Exit::Debug {
+        debugger::debug(&mut chip_eight, fault, &input)?;
+    }
+    return Ok(());
+}
+
+// Run until the player quits or asks for the debugger, handing back the fault that stopped the
+// machine, if any. A fault goes straight to the debugger unless `debug_on_fault` is off, then it
+// can be rewound or loaded out of.
+fn play(chip_eight: &mut ChipEight, options: &TerminalOptions, input: &Receiver<Vec<u8>>,
+        reports: &mut Vec<CrashReport>) -> Result<(Exit, Option<EmulatorError>), Error> {
+    let _terminal: RawTerminal = RawTerminal::enter()?;
+    let keymap: Keymap = options.keymap.keymap_for(&chip_eight.rom_sha1());
+    let status: StatusLines = StatusLines::new(options.scale);
+    let mut slot: u8 = 0;
+    status.show(&format!(
+        "slot {}   F5 save  F6 next slot  F7 load  Backspace rewind  F8 debug  Esc quit", slot));
+    let mut rewind: Rewind = Rewind::new(options.rewind_budget);
+    let mut fault: Option<EmulatorError> = None;
+
+    // When each held key was last reported by the terminal, Backspace included.
+    let mut last_seen: [Option<Instant>; 16] = [None; 16];
+    let mut rewind_seen: Option<Instant> = None;
+    loop {
+        loop {
+            let bytes: Vec<u8> = match input.try_recv() {
+                Ok(bytes) => bytes,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok((Exit::Quit, fault)),
+            };
+            for input in parse_input(&bytes, &keymap) {
+                match input {
+                    Input::Quit => return Ok((Exit::Quit, fault)),
+                    Input::Debug => return Ok((Exit::Debug, fault)),
+                    Input::Key(key) => {
+                        chip_eight.queue_key(key, Position::DOWN);
+                        last_seen[key as usize] = Some(Instant::now());
+                    }
+                    Input::SaveState => match save_slot(chip_eight, options, slot) {
+                        Ok(()) => status.show(&format!("slot {}   saved", slot)),
+                        Err(error) =>
+                            status.show(&format!("slot {}   save failed: {}", slot, error)),
+                    },
+                    Input::Rewind => rewind_seen = Some(Instant::now()),
+                    Input::NextSlot => {
+                        slot = (slot + 1) % SLOTS;
+                        status.show(&format!("slot {}", slot));
+                    }
+                    Input::LoadState => match load_slot(chip_eight, options, slot) {
+                        Ok(()) => {
+                            // The keys held now win over the ones held when the state was saved.
+                            for (key, seen) in Key::ALL.into_iter().zip(last_seen) {
+                                let position: Position =
+                                    if seen.is_some() {Position::DOWN} else {Position::UP};
+                                chip_eight.queue_key(key, position);
+                            }
+                            if fault.take().is_some() { status.clear_fault(); }
+                            status.show(&format!("slot {}   loaded", slot));
+                        }
+                        Err(error) =>
+                            status.show(&format!("slot {}   load failed: {}", slot, error)),
+                    },
+                }
+            }
+        }
+        // Released as of when the timeout ran out, not when we got round to noticing.
+        for (key, seen) in Key::ALL.into_iter().zip(last_seen.iter_mut()) {
+            if let Some(at) = seen.filter(|at| at.elapsed() >= options.key_release_timeout) {
+                let at: Instant = at + options.key_release_timeout;
+                chip_eight.queue_event(KeyEvent { key, position: Position::UP, at });
+                *seen = None;
+            }
+        }
+        if rewind_seen.is_some_and(|at| at.elapsed() >= options.key_release_timeout) {
+            rewind_seen = None;
+            status.show(&format!("slot {}", slot));
+        }
+
+        if rewind_seen.is_some() {
+            match rewind.step_back(chip_eight) {
+                Ok(true) => {
+                    if fault.take().is_some() { status.clear_fault(); }
+                    status.show(&format!("slot {}   rewinding, {} frames left", slot,
+                        rewind.frames()));
+                }
+                Ok(false) => status.show(&format!("slot {}   nothing left to rewind", slot)),
+                Err(error) => status.show(&format!("slot {}   rewind failed: {}", slot, error)),
+            }
+            chip_eight.show_frame();
+        } else if fault.is_some() {
+            thread::sleep(Duration::from_millis(16));
+        } else {
+            if options.rewind_budget > 0 { rewind.record(chip_eight); }
+            if let Err(error) = chip_eight.run_frame() {
+                reports.push(chip_eight.crash_report(error));
+                if options.debug_on_fault { return Ok((Exit::Debug, Some(error))); }
+                status.show_fault(&error);
+                fault = Some(error);
+            }
+        }
+    }
+}
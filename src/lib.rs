@@ -0,0 +1,17 @@
+// The repo favours explicit returns and unit return types.
+#![allow(clippy::needless_return, clippy::unused_unit, clippy::upper_case_acronyms)]
+// `is_multiple_of` and `is_none_or` are too new for the toolchains the crate builds on.
+#![allow(unknown_lints, clippy::manual_is_multiple_of, clippy::unnecessary_map_or)]
+
+pub mod chip_eight_core;
+pub mod common;
+
+// What most embedders need, the rest is under `chip_eight_core`.
+pub use chip_eight_core::api::{Audio, Display, Frame, MachineState, Region, StepOutcome};
+pub use chip_eight_core::builder::ChipEightBuilder;
+pub use chip_eight_core::chip_eight::ChipEight;
+pub use chip_eight_core::error::EmulatorError;
+pub use chip_eight_core::keypad::Key;
+pub use chip_eight_core::quirks::{QuirkProfile, Quirks};
+pub use chip_eight_core::random::RandomMode;
+pub use chip_eight_core::scheduler::{Pacing, Speed};
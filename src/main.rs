@@ -1,17 +1,435 @@
-mod common;
-mod chip_eight_core;
+// The repo favours explicit returns and unit return types.
+#![allow(clippy::needless_return, clippy::unused_unit, clippy::upper_case_acronyms)]
 
-use chip_eight_core::chip_eight::ChipEight;
-use common::file_utils::read_in_rom;
+mod frontends;
 
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use chip_eight::chip_eight_core::assembler;
+use chip_eight::chip_eight_core::builder::ChipEightBuilder;
+use chip_eight::chip_eight_core::chip_eight::ChipEight;
+use chip_eight::chip_eight_core::disassembler::{self, Disassembly};
+use chip_eight::chip_eight_core::movie::PlaybackResult;
+use chip_eight::chip_eight_core::quirks::{QuirkProfile, Quirks};
+use chip_eight::chip_eight_core::random::RandomMode;
+use chip_eight::chip_eight_core::rom_info;
+use chip_eight::chip_eight_core::scheduler::Speed;
+use chip_eight::common::file_utils::{describe_read_error, read_in_rom};
+use chip_eight::common::hex_utils;
+use chip_eight::common::image_utils::{self, ImageFormat, Palette};
+use frontends::debugger;
+use frontends::headless::{self, HeadlessOptions, HeadlessReport, StopReason};
+use frontends::keymap::{self, KeymapConfig};
+use frontends::terminal::{self, TerminalOptions};
+
+const USAGE: &str = "\
+usage: chip_eight <command> [arguments]
+
+commands:
+  run <rom> [--debug]          play a ROM, or step through it in the debugger, which a
+                               fault also drops into, as does F8 in the terminal frontend;
+      [--no-debug-on-fault]    leave a fault on screen to rewind, or just report it, instead
+  info <rom>                   size, SHA-1, likely platform and opcode statistics
+  disasm <rom> [--recursive]   disassemble, following the code from the entry point
+  asm <source> [-o <rom>]      assemble a source file
+  test <rom>                   run headless and check it does not fault, or that the
+                               final frame matches --expect <image>; without one it also
+                               fails if the ROM is left waiting for a key
+  replay <rom> <movie>         play back a recorded movie headless, checking it stays in sync,
+         [-o <image>]          and write the final frame if asked to
+
+options for run and test:
+  --ips <n>                    instructions per second (default 700)
+  --quirks <profile>           cosmac-vip (default), chip-48, schip-modern, schip-legacy,
+                               xo-chip
+  --seed <n>                   seed for CXNN, picked at random when not given
+  --random <mode>              xorshift (default) or table
+  --scale <n>                  size of a pixel, in terminal cells or image pixels
+  --palette <RRGGBB:RRGGBB>    lit and unlit colours
+  --frontend <name>            terminal (default) or headless, for run
+  --output <image>             where to write the final frame as .ppm or .pbm, for headless
+  --cycles <n>                 instructions to run at most, for headless (default 1000000)
+  --keymap <file>              keymap config, for terminal
+                               (default ~/.config/chip_eight/keymap.conf)
+  --record <movie>             record the session as a movie, for terminal
+  --key-release-ms <n>         how long a key stays down after the terminal last reports it,
+                               for terminal (default 250)
+  --crash-report <file>        also write the report to a file if the ROM faults
+";
+
+// The options run and test share.
+const MACHINE_OPTIONS: [&str; 9] = [
+    "--ips", "--quirks", "--seed", "--random", "--scale", "--palette", "--output", "--cycles",
+    "--crash-report",
+];
 
 fn main() {
-    let rom: Vec<u8> =
-        read_in_rom("/Users/christophercimponeriu/repos/chip8-test-suite/bin/1-chip8-logo.ch8")
-            .expect("rom failed to load from file system.");
-
-    let chip_eight: ChipEight = ChipEight::new();
-    // TODO: pass in display type so that can be played in GUI, Terminal, or Browser.
-    //  Pass in keyboard settings. Create a config.
-    chip_eight.run(&rom);
+    let arguments: Vec<String> = env::args().skip(1).collect();
+    let rest: &[String] = arguments.get(1..).unwrap_or_default();
+    match arguments.first().map(String::as_str) {
+        Some("run") => run(rest),
+        Some("info") => info(rest),
+        Some("disasm") => disasm(rest),
+        Some("asm") => asm(rest),
+        Some("test") => test(rest),
+        Some("replay") => replay(rest),
+        Some("help" | "--help" | "-h") => print!("{}", USAGE),
+        Some(command) => fail(&format!("unknown command '{}'", command)),
+        None => {
+            eprint!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}\nrun `chip_eight help` for usage.", message);
+    process::exit(2);
+}
+
+// A subcommand's positional arguments, `--name value` options and `--name` switches.
+struct Arguments {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    switches: Vec<String>,
+}
+
+impl Arguments {
+    fn parse(arguments: &[String], options: &[&str], switches: &[&str]) -> Arguments {
+        let mut parsed: Arguments =
+            Arguments { positional: Vec::new(), options: HashMap::new(), switches: Vec::new() };
+        let mut arguments = arguments.iter();
+        while let Some(argument) = arguments.next() {
+            if options.contains(&argument.as_str()) {
+                let Some(value) = arguments.next() else {
+                    fail(&format!("{} needs a value", argument));
+                };
+                parsed.options.insert(argument.clone(), value.clone());
+            } else if switches.contains(&argument.as_str()) {
+                parsed.switches.push(argument.clone());
+            } else if argument.starts_with('-') && argument.len() > 1 {
+                fail(&format!("unknown option '{}'", argument));
+            } else {
+                parsed.positional.push(argument.clone());
+            }
+        }
+        return parsed;
+    }
+    // The single positional argument, e.g. the ROM.
+    fn only(&self, what: &str) -> &str {
+        return match self.positional.as_slice() {
+            [argument] => argument,
+            [] => fail(&format!("expected a {}", what)),
+            _ => fail(&format!("expected a single {}, got '{}'", what, self.positional.join(" "))),
+        };
+    }
+    fn option(&self, name: &str) -> Option<&str> {
+        return self.options.get(name).map(String::as_str);
+    }
+    fn switch(&self, name: &str) -> bool {
+        return self.switches.iter().any(|switch| switch == name);
+    }
+    fn number<T: FromStr>(&self, name: &str) -> Option<T> {
+        let value: &str = self.option(name)?;
+        return match value.parse() {
+            Ok(number) => Some(number),
+            Err(_) => fail(&format!("{} expects a number, got '{}'", name, value)),
+        };
+    }
+}
+
+fn read_rom(path: &str) -> Vec<u8> {
+    return match read_in_rom(path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}", describe_read_error(path, &error));
+            process::exit(1);
+        }
+    };
+}
+
+// How to set up the machine, from the options run and test share.
+struct MachineOptions {
+    quirks: Quirks,
+    speed: Speed,
+    seed: Option<u64>,
+    random_mode: RandomMode,
+    scale: usize,
+    palette: Option<Palette>,
+    crash_report: Option<String>,
+}
+
+impl MachineOptions {
+    fn parse(arguments: &Arguments) -> MachineOptions {
+        let quirks: Quirks = match arguments.option("--quirks") {
+            Some(name) => QuirkProfile::from_name(name).unwrap_or_else(|| {
+                let names: Vec<&str> = QuirkProfile::ALL.iter().map(|profile| profile.name())
+                    .collect();
+                fail(&format!("unknown quirk profile '{}', expected one of: {}",
+                    name, names.join(", ")))
+            }).quirks(),
+            None => Quirks::new(),
+        };
+        let speed: Speed = match arguments.number::<u32>("--ips") {
+            Some(0) => fail("--ips must be at least 1"),
+            Some(instructions) => Speed::InstructionsPerSecond(instructions),
+            None => Speed::InstructionsPerSecond(700),
+        };
+        let seed: Option<u64> = arguments.option("--seed").map(|seed| hex_utils::parse_number(seed)
+            .unwrap_or_else(|| fail(&format!("--seed expects a number, got '{}'", seed))));
+        let random_mode: RandomMode = match arguments.option("--random") {
+            Some(name) => RandomMode::from_name(name).unwrap_or_else(|| fail(&format!(
+                "unknown random mode '{}', expected xorshift or table", name))),
+            None => RandomMode::Xorshift,
+        };
+        let scale: usize = match arguments.number::<usize>("--scale") {
+            Some(0) => fail("--scale must be at least 1"),
+            Some(scale) => scale,
+            None => 1,
+        };
+        let palette: Option<Palette> = arguments.option("--palette").map(|palette|
+            Palette::parse(palette).unwrap_or_else(|| fail(&format!(
+                "--palette expects RRGGBB:RRGGBB, got '{}'", palette))));
+        return MachineOptions {
+            quirks, speed, seed, random_mode, scale, palette,
+            crash_report: arguments.option("--crash-report").map(str::to_string),
+        };
+    }
+    fn builder(&self) -> ChipEightBuilder {
+        let builder: ChipEightBuilder = ChipEightBuilder::new()
+            .quirks(self.quirks)
+            .speed(self.speed)
+            .random_mode(self.random_mode);
+        return match self.seed {
+            Some(seed) => builder.seed(seed),
+            None => builder,
+        };
+    }
+    fn headless(&self, arguments: &Arguments, default_output: &str) -> HeadlessOptions {
+        let mut options: HeadlessOptions =
+            HeadlessOptions::new(arguments.option("--output").unwrap_or(default_output));
+        options.quirks = self.quirks;
+        options.speed = self.speed;
+        options.seed = self.seed;
+        options.random_mode = self.random_mode;
+        options.scale = self.scale;
+        options.palette = self.palette.unwrap_or(options.palette);
+        options.crash_report = self.crash_report.clone();
+        if let Some(cycles) = arguments.number("--cycles") {
+            options.cycles = cycles;
+        }
+        if ImageFormat::from_path(&options.output).is_none() {
+            fail(&format!("--output must end in .ppm or .pbm, got '{}'", options.output));
+        }
+        return options;
+    }
+}
+
+// run <rom> [options]
+fn run(arguments: &[String]) {
+    let mut options: Vec<&str> = MACHINE_OPTIONS.to_vec();
+    options.extend(["--frontend", "--keymap", "--record", "--key-release-ms"]);
+    let arguments: Arguments =
+        Arguments::parse(arguments, &options, &["--debug", "--no-debug-on-fault"]);
+    let path: &str = arguments.only("ROM");
+    let machine: MachineOptions = MachineOptions::parse(&arguments);
+    let rom: Vec<u8> = read_rom(path);
+    let debug_on_fault: bool = !arguments.switch("--no-debug-on-fault");
+
+    if arguments.switch("--debug") {
+        let mut chip_eight: ChipEight = machine.builder().build();
+        if let Err(error) = chip_eight.load_rom(&rom) {
+            eprintln!("could not load ROM: {}", error);
+            process::exit(1);
+        }
+        if let Err(error) = debugger::run(&mut chip_eight) {
+            eprintln!("debugger failed: {}", error);
+            process::exit(1);
+        }
+        return;
+    }
+    match arguments.option("--frontend").unwrap_or("terminal") {
+        "terminal" => {
+            let mut options: TerminalOptions = TerminalOptions::new();
+            options.quirks = machine.quirks;
+            options.speed = machine.speed;
+            options.seed = machine.seed;
+            options.random_mode = machine.random_mode;
+            options.scale = machine.scale;
+            options.palette = machine.palette;
+            options.crash_report = machine.crash_report;
+            options.debug_on_fault = debug_on_fault;
+            options.record = arguments.option("--record").map(PathBuf::from);
+            options.keymap = load_keymap(arguments.option("--keymap"));
+            match arguments.number::<u64>("--key-release-ms") {
+                Some(0) => fail("--key-release-ms must be at least 1"),
+                Some(milliseconds) =>
+                    options.key_release_timeout = Duration::from_millis(milliseconds),
+                None => {}
+            }
+            if let Err(error) = terminal::run(&rom, &options) {
+                eprintln!("terminal frontend failed: {}", error);
+                process::exit(1);
+            }
+        }
+        "headless" => {
+            let options: HeadlessOptions = machine.headless(&arguments, "out.ppm");
+            match headless::run(&rom, &options) {
+                Ok((mut chip_eight, report)) => {
+                    println!("{} after {} cycles, wrote {}",
+                        report.reason, report.cycles, options.output);
+                    let StopReason::Fault(error) = report.reason else { return; };
+                    if debug_on_fault {
+                        let input: Receiver<Vec<u8>> = terminal::spawn_input_reader();
+                        if let Err(error) = debugger::debug(&mut chip_eight, Some(error), &input) {
+                            eprintln!("debugger failed: {}", error);
+                        }
+                    }
+                    process::exit(1);
+                }
+                Err(error) => {
+                    eprintln!("headless run failed: {}", error);
+                    process::exit(1);
+                }
+            }
+        }
+        frontend => fail(&format!("unknown frontend '{}', expected terminal or headless",
+            frontend)),
+    }
+}
+
+// An explicit keymap has to load, the default one only if it is there.
+fn load_keymap(path: Option<&str>) -> KeymapConfig {
+    let path: String = match path {
+        Some(path) => path.to_string(),
+        None => match keymap::default_config_path().filter(|path| path.exists()) {
+            Some(path) => path.to_string_lossy().into_owned(),
+            None => return KeymapConfig::new(),
+        },
+    };
+    return KeymapConfig::load(&path).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+}
+
+// info <rom>
+fn info(arguments: &[String]) {
+    let arguments: Arguments = Arguments::parse(arguments, &[], &[]);
+    let rom: Vec<u8> = read_rom(arguments.only("ROM"));
+    print!("{}", rom_info::analyze(&rom).render());
+}
+
+// disasm <rom> [--recursive]
+fn disasm(arguments: &[String]) {
+    let arguments: Arguments = Arguments::parse(arguments, &[], &["--recursive"]);
+    let rom: Vec<u8> = read_rom(arguments.only("ROM"));
+    let disassembly: Disassembly = if arguments.switch("--recursive") {
+        disassembler::recursive(&rom)
+    } else {
+        disassembler::linear(&rom)
+    };
+    print!("{}", disassembly.render());
+}
+
+// asm <source> [-o <output>]
+fn asm(arguments: &[String]) {
+    let arguments: Arguments = Arguments::parse(arguments, &["-o"], &[]);
+    let source: &str = arguments.only("source file");
+    let output: String = match arguments.option("-o") {
+        Some(output) => output.to_string(),
+        None => source.rsplit_once('.').map_or(source, |(stem, _)| stem).to_string() + ".ch8",
+    };
+    let rom: Vec<u8> = match assembler::assemble_file(source) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    if let Err(error) = fs::write(&output, &rom) {
+        eprintln!("could not write {}: {}", output, error);
+        process::exit(1);
+    }
+    println!("wrote {} bytes to {}", rom.len(), output);
+}
+
+// test <rom> [--expect <image>] [options]
+fn test(arguments: &[String]) {
+    let mut options: Vec<&str> = MACHINE_OPTIONS.to_vec();
+    options.push("--expect");
+    let arguments: Arguments = Arguments::parse(arguments, &options, &[]);
+    let path: &str = arguments.only("ROM");
+    let machine: MachineOptions = MachineOptions::parse(&arguments);
+    let expect: Option<&str> = arguments.option("--expect");
+    // The frame is compared in the format of the expected image, and only written when asked.
+    let options: HeadlessOptions = machine.headless(&arguments, expect.unwrap_or("out.ppm"));
+    let rom: Vec<u8> = read_rom(path);
+
+    let (chip_eight, report): (_, HeadlessReport) = match headless::execute(&rom, &options) {
+        Ok(result) => result,
+        Err(error) => {
+            eprintln!("test run failed: {}", error);
+            process::exit(1);
+        }
+    };
+    let mut problems: Vec<String> = Vec::new();
+    problems.extend(report.reason.problem(expect.is_some()));
+    if let Some(expect) = expect {
+        let format: ImageFormat = ImageFormat::from_path(expect).unwrap_or_else(||
+            fail(&format!("--expect must end in .ppm or .pbm, got '{}'", expect)));
+        let mut actual: Vec<u8> = Vec::new();
+        image_utils::encode_frame(&mut actual, &chip_eight.framebuffer(), options.scale,
+            options.palette, format).expect("writing to memory cannot fail.");
+        match fs::read(expect) {
+            Ok(expected) if expected == actual => {}
+            Ok(_) => problems.push(format!("final frame differs from {}", expect)),
+            Err(error) => problems.push(format!("could not read {}: {}", expect, error)),
+        }
+    }
+    if arguments.option("--output").is_some() {
+        let written = image_utils::write_frame(&options.output, &chip_eight.framebuffer(),
+            options.scale, options.palette);
+        if let Err(error) = written {
+            problems.push(format!("could not write {}: {}", options.output, error));
+        }
+    }
+
+    let verdict: &str = if problems.is_empty() {"PASS"} else {"FAIL"};
+    println!("{} {} ({} after {} cycles)", verdict, path, report.reason, report.cycles);
+    for problem in &problems {
+        println!("  {}", problem);
+    }
+    if !problems.is_empty() { process::exit(1); }
+}
+
+// replay <rom> <movie> [-o <image>]
+fn replay(arguments: &[String]) {
+    let arguments: Arguments = Arguments::parse(arguments, &["-o"], &[]);
+    let [rom, movie] = arguments.positional.as_slice() else {
+        fail("expected a ROM and a movie");
+    };
+    let rom: Vec<u8> = read_rom(rom);
+    let movie: Vec<u8> = fs::read(movie).unwrap_or_else(|error| {
+        eprintln!("could not read movie '{}': {}", movie, error);
+        process::exit(1);
+    });
+    match headless::replay(&rom, &movie, arguments.option("-o")) {
+        Ok(result @ PlaybackResult::Completed { .. }) => println!("{}", result),
+        Ok(result) => {
+            println!("{}", result);
+            process::exit(1);
+        }
+        Err(error) => {
+            eprintln!("replay failed: {}", error);
+            process::exit(1);
+        }
+    }
 }